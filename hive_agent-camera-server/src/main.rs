@@ -3,7 +3,6 @@ use actix_cors::Cors;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use std::fs;
-use std::env;
 use anyhow::Result;
 use image::DynamicImage;
 use nokhwa::{Camera, query};
@@ -48,40 +47,6 @@ impl AppState {
     }
 }
 
-/// Get the service port from warden or use default
-fn get_service_port() -> u16 {
-    // Priority order:
-    // 1. Command line argument --port
-    // 2. Environment variable WARDEN_ASSIGNED_PORT
-    // 3. Environment variable SERVICE_PORT  
-    // 4. Default port
-    
-    // Check command line arguments
-    let args: Vec<String> = env::args().collect();
-    for i in 0..args.len() {
-        if args[i] == "--port" && i + 1 < args.len() {
-            if let Ok(port) = args[i + 1].parse::<u16>() {
-                return port;
-            }
-        }
-    }
-    
-    // Check environment variables
-    if let Ok(port_str) = env::var("WARDEN_ASSIGNED_PORT") {
-        if let Ok(port) = port_str.parse::<u16>() {
-            return port;
-        }
-    }
-    
-    if let Ok(port_str) = env::var("SERVICE_PORT") {
-        if let Ok(port) = port_str.parse::<u16>() {
-            return port;
-        }
-    }
-    
-    DEFAULT_PORT
-}
-
 /// Get the current platform as a string
 fn get_platform() -> String {
     if cfg!(target_os = "linux") {
@@ -324,7 +289,7 @@ async fn status(data: web::Data<Arc<Mutex<AppState>>>) -> HttpResponse {
     HttpResponse::Ok().json(serde_json::json!({
         "service": SERVICE_NAME,
         "status": if camera_active { "operational" } else { "degraded" },
-        "version": "0.1.0",
+        "version": env!("CARGO_PKG_VERSION"),
         "platform": get_platform(),
         "camera_active": camera_active,
         "camera_index": state.camera_index,
@@ -379,7 +344,7 @@ async fn list_cameras() -> HttpResponse {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let port = get_service_port();
+    let port = hive_agent_common::service_port(DEFAULT_PORT);
     
     println!("🚀 Starting {} on port {}", SERVICE_NAME, port);
     println!("📁 Output directory: generated_image_captures");
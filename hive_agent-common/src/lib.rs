@@ -0,0 +1,16 @@
+// Shared helpers for the services the warden supervises
+use std::env;
+
+/// The port to listen on: the one the warden assigned, or `default_port`
+///
+/// Checked in order: a `--port` argument, then `WARDEN_ASSIGNED_PORT`, then `SERVICE_PORT`.
+pub fn service_port(default_port: u16) -> u16 {
+    env::args()
+        .collect::<Vec<String>>()
+        .windows(2)
+        .find(|w| w[0] == "--port")
+        .and_then(|w| w[1].parse().ok())
+        .or_else(|| env::var("WARDEN_ASSIGNED_PORT").ok()?.parse().ok())
+        .or_else(|| env::var("SERVICE_PORT").ok()?.parse().ok())
+        .unwrap_or(default_port)
+}
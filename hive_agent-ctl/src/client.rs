@@ -0,0 +1,164 @@
+// Warden client - where the warden is, how to authenticate, and the HTTP calls themselves
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    time::Duration,
+};
+
+const DEFAULT_URL: &str = "http://localhost:6080";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const API_PREFIX: &str = "/api/v1/warden";
+
+/// Optional `~/.config/hive/ctl.json`
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    url: Option<String>,
+    token: Option<String>,
+}
+
+/// Where the config file is: `HIVE_CTL_CONFIG`, else the user's config directory
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("HIVE_CTL_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+    Some(config_home.join("hive").join("ctl.json"))
+}
+
+fn load_config_file() -> Result<ConfigFile> {
+    let path = match config_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(ConfigFile::default()),
+    };
+    let contents = fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+}
+
+pub struct WardenClient {
+    base_url: String,
+    token: Option<String>,
+    http: Client,
+}
+
+impl WardenClient {
+    /// Settle the URL and token: command-line flags, then `HIVE_WARDEN_URL` /
+    /// `HIVE_WARDEN_TOKEN`, then the config file, then the local default
+    pub fn new(url: Option<String>, token: Option<String>) -> Result<Self> {
+        let file = load_config_file()?;
+        let base_url = url
+            .or_else(|| env::var("HIVE_WARDEN_URL").ok())
+            .or(file.url)
+            .unwrap_or_else(|| DEFAULT_URL.to_string());
+        let token = token
+            .or_else(|| env::var("HIVE_WARDEN_TOKEN").ok())
+            .or(file.token)
+            .filter(|token| !token.is_empty());
+        // No client-wide timeout so event and log streams can stay open
+        let http = Client::builder().timeout(None).build()?;
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+            http,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}{}", self.base_url, API_PREFIX, path)
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Send a request and return its JSON body, turning error statuses into errors
+    fn call(&self, request: RequestBuilder) -> Result<Value> {
+        self.call_untimed(request.timeout(REQUEST_TIMEOUT))
+    }
+
+    fn call_untimed(&self, request: RequestBuilder) -> Result<Value> {
+        let response = self
+            .authorized(request)
+            .send()
+            .with_context(|| format!("cannot reach the warden at {}", self.base_url))?;
+        let response = check_status(response)?;
+        let body = response.text()?;
+        serde_json::from_str(&body).context("the warden sent an invalid response")
+    }
+
+    pub fn get(&self, path: &str) -> Result<Value> {
+        self.call(self.http.get(self.url(path)))
+    }
+
+    pub fn post(&self, path: &str, body: Option<Value>) -> Result<Value> {
+        let request = self.http.post(self.url(path));
+        let request = match body {
+            Some(body) => request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string()),
+            None => request,
+        };
+        self.call(request)
+    }
+
+    /// POST to an endpoint that answers once services have come up, however long that takes
+    pub fn post_and_wait(&self, path: &str) -> Result<Value> {
+        self.call_untimed(self.http.post(self.url(path)))
+    }
+
+    /// Follow a Server-Sent Events stream, calling `on_event` with each event's JSON data
+    ///
+    /// Returns when the warden closes the stream.
+    pub fn stream(&self, path: &str, mut on_event: impl FnMut(Value) -> Result<()>) -> Result<()> {
+        let request = self
+            .http
+            .get(self.url(path))
+            .header(reqwest::header::ACCEPT, "text/event-stream");
+        let response = self
+            .authorized(request)
+            .send()
+            .with_context(|| format!("cannot reach the warden at {}", self.base_url))?;
+        let response = check_status(response)?;
+
+        let mut data = String::new();
+        for line in BufReader::new(response).lines() {
+            let line = line.context("the event stream broke off")?;
+            if let Some(value) = line.strip_prefix("data:") {
+                if !data.is_empty() {
+                    data.push('\n');
+                }
+                data.push_str(value.trim_start());
+            } else if line.is_empty() && !data.is_empty() {
+                let event = serde_json::from_str(&data).context("the warden sent an invalid event")?;
+                data.clear();
+                on_event(event)?;
+            }
+            // `id:`, `event:` and `:` keepalive lines need no handling
+        }
+        Ok(())
+    }
+}
+
+/// Turn a non-2xx answer into an error carrying the warden's message
+fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|value| value["message"].as_str().map(str::to_string))
+        .unwrap_or(body);
+    Err(anyhow!("{} ({})", message.trim(), status))
+}
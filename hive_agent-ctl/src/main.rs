@@ -0,0 +1,409 @@
+// hive-ctl - command-line control client for the Hive Agent Warden
+mod client;
+mod output;
+
+use anyhow::Result;
+use client::WardenClient;
+use serde_json::{json, Value};
+use std::{env, process};
+
+const USAGE: &str = "\
+Usage: hive-ctl [--url URL] [--token TOKEN] [--json] <command>
+
+Commands:
+  status                                 Warden status
+  services                               Every service with its state
+  enable <service>                       Enable and start a service
+  disable <service>                      Stop and disable a service
+  upgrade <service>                      Switch a service to its rebuilt binary without downtime
+  logs <service> [-n N] [-f]             Recent output; -f keeps following
+  port check <port>                      Whether a port is in use or leased
+  port allocate <owner> [--preferred P] [--ttl SECS]
+                                         Lease a port
+  reload                                 Re-read core_microservices.json
+  profiles                               Configured profiles; * marks the active one
+  profile activate <name>                Enable exactly the services of a profile
+  events [--since SEQ] [--service S] [--type T1,T2]
+                                         Follow lifecycle events
+
+The warden URL and token come from --url/--token, then HIVE_WARDEN_URL and
+HIVE_WARDEN_TOKEN, then ~/.config/hive/ctl.json ({\"url\": ..., \"token\": ...},
+or the file named by HIVE_CTL_CONFIG). The URL defaults to http://localhost:6080.";
+
+/// A mistake on the command line rather than a failed call
+#[derive(Debug)]
+struct UsageError(String);
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+fn usage_error(message: impl Into<String>) -> anyhow::Error {
+    UsageError(message.into()).into()
+}
+
+/// Remove `--name VALUE` (or `--name=VALUE`) from the arguments
+fn take_option(args: &mut Vec<String>, names: &[&str]) -> Result<Option<String>> {
+    let mut i = 0;
+    while i < args.len() {
+        if names.contains(&args[i].as_str()) {
+            if i + 1 >= args.len() {
+                return Err(usage_error(format!("{} needs a value", args[i])));
+            }
+            let value = args.remove(i + 1);
+            args.remove(i);
+            return Ok(Some(value));
+        }
+        if let Some((name, value)) = args[i].split_once('=') {
+            if names.contains(&name) {
+                let value = value.to_string();
+                args.remove(i);
+                return Ok(Some(value));
+            }
+        }
+        i += 1;
+    }
+    Ok(None)
+}
+
+/// Remove a boolean flag from the arguments, reporting whether it was there
+fn take_flag(args: &mut Vec<String>, names: &[&str]) -> bool {
+    let before = args.len();
+    args.retain(|arg| !names.contains(&arg.as_str()));
+    args.len() != before
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| usage_error(format!("invalid {}: {}", what, value)))
+}
+
+/// The single word the warden uses for a service's health
+fn service_state(service: &Value) -> &'static str {
+    let flag = |name: &str| service[name].as_bool().unwrap_or(false);
+    if !flag("enabled") {
+        "disabled"
+    } else if flag("failed") {
+        "failed"
+    } else if flag("healthy") {
+        "healthy"
+    } else if flag("degraded") {
+        "degraded"
+    } else if flag("starting") {
+        "starting"
+    } else if flag("running") {
+        "unhealthy"
+    } else {
+        "stopped"
+    }
+}
+
+fn status(client: &WardenClient, as_json: bool) -> Result<()> {
+    let status = client.get("/status")?;
+    if as_json {
+        output::json(&status);
+    } else {
+        output::fields(&status);
+    }
+    Ok(())
+}
+
+fn services(client: &WardenClient, as_json: bool) -> Result<()> {
+    let services = client.get("/services")?;
+    if as_json {
+        output::json(&services);
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = services
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|service| {
+            let memory = service["resource_usage"]["memory_bytes"]
+                .as_u64()
+                .map_or_else(|| "-".to_string(), output::bytes);
+            let port = match &service["running_port"] {
+                Value::Null => &service["port"],
+                running => running,
+            };
+            vec![
+                output::cell(&service["name"]),
+                output::cell(port),
+                output::cell(&service["version"]),
+                service_state(service).to_string(),
+                memory,
+                output::cell(&service["failure_reason"]),
+            ]
+        })
+        .collect();
+    output::table(&["NAME", "PORT", "VERSION", "STATE", "MEMORY", "REASON"], &rows);
+    Ok(())
+}
+
+/// enable / disable
+fn toggle(client: &WardenClient, action: &str, name: &str, as_json: bool) -> Result<()> {
+    let response = client.post(&format!("/service/{}/{}", name, action), None)?;
+    if as_json {
+        output::json(&response);
+    } else {
+        println!("{}", output::cell(&response["message"]));
+    }
+    Ok(())
+}
+
+fn upgrade(client: &WardenClient, name: &str, as_json: bool) -> Result<()> {
+    let response = client.post_and_wait(&format!("/service/{}/upgrade", name))?;
+    if as_json {
+        output::json(&response);
+        return Ok(());
+    }
+    println!("{}", output::cell(&response["message"]));
+    for unit in response["units"].as_array().into_iter().flatten() {
+        println!(
+            "  {}: port {} -> {}, version {} -> {}",
+            output::cell(&unit["name"]),
+            output::cell(&unit["from_port"]),
+            output::cell(&unit["to_port"]),
+            output::cell(&unit["previous_version"]),
+            output::cell(&unit["version"])
+        );
+    }
+    Ok(())
+}
+
+fn print_log_line(line: &Value, as_json: bool) {
+    if as_json {
+        println!("{}", line);
+    } else if line["stream"] == "stderr" {
+        println!("[stderr] {}", output::cell(&line["line"]));
+    } else {
+        println!("{}", output::cell(&line["line"]));
+    }
+}
+
+fn logs(client: &WardenClient, mut args: Vec<String>, as_json: bool) -> Result<()> {
+    let tail: usize = match take_option(&mut args, &["-n", "--tail"])? {
+        Some(value) => parse_number(&value, "line count")?,
+        None => 100,
+    };
+    let follow = take_flag(&mut args, &["-f", "--follow"]);
+    let name = match args.as_slice() {
+        [name] => name.clone(),
+        _ => return Err(usage_error("logs takes one service name")),
+    };
+
+    if follow {
+        return client.stream(&format!("/service/{}/logs?tail={}&follow=true", name, tail), |line| {
+            print_log_line(&line, as_json);
+            Ok(())
+        });
+    }
+    let response = client.get(&format!("/service/{}/logs?tail={}", name, tail))?;
+    if as_json {
+        output::json(&response);
+        return Ok(());
+    }
+    for line in response["lines"].as_array().into_iter().flatten() {
+        print_log_line(line, false);
+    }
+    Ok(())
+}
+
+fn port(client: &WardenClient, mut args: Vec<String>, as_json: bool) -> Result<()> {
+    if args.first().map(String::as_str) == Some("allocate") {
+        let preferred = take_option(&mut args, &["--preferred"])?;
+        let ttl = take_option(&mut args, &["--ttl"])?;
+        let owner = match &args[1..] {
+            [owner] => owner.clone(),
+            _ => return Err(usage_error("port allocate takes one owner name")),
+        };
+        let mut body = json!({ "service_name": owner });
+        if let Some(preferred) = preferred {
+            body["preferred_port"] = json!(parse_number::<u16>(&preferred, "port")?);
+        }
+        if let Some(ttl) = ttl {
+            body["ttl_secs"] = json!(parse_number::<u64>(&ttl, "TTL")?);
+        }
+        let response = client.post("/port/allocate", Some(body))?;
+        if as_json {
+            output::json(&response);
+        } else {
+            let port = response["lease"]["port"].clone();
+            match response["requested_port"].as_u64() {
+                Some(requested) if response["status"] == "reassigned" => {
+                    println!("{} (port {} was taken)", output::cell(&port), requested)
+                }
+                _ => println!("{}", output::cell(&port)),
+            }
+        }
+        return Ok(());
+    }
+
+    match args.as_slice() {
+        [command, port] if command == "check" => {
+            let port: u16 = parse_number(port, "port")?;
+            let response = client.get(&format!("/port/check/{}", port))?;
+            if as_json {
+                output::json(&response);
+            } else {
+                output::fields(&response);
+            }
+            Ok(())
+        }
+        _ => Err(usage_error("port takes `check <port>` or `allocate <owner>`")),
+    }
+}
+
+fn reload(client: &WardenClient, as_json: bool) -> Result<()> {
+    let response = client.post("/reload", None)?;
+    if as_json {
+        output::json(&response);
+        return Ok(());
+    }
+    println!("{}", output::cell(&response["message"]));
+    for change in ["added", "removed", "changed"] {
+        let names = &response["changes"][change];
+        if names.as_array().is_some_and(|names| !names.is_empty()) {
+            println!("  {}: {}", change, output::cell(names));
+        }
+    }
+    Ok(())
+}
+
+fn profiles(client: &WardenClient, as_json: bool) -> Result<()> {
+    let response = client.get("/profiles")?;
+    if as_json {
+        output::json(&response);
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = response["profiles"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|profile| {
+            let marker = if profile["active"] == true { "*" } else { "" };
+            vec![
+                marker.to_string(),
+                output::cell(&profile["name"]),
+                output::cell(&profile["services"]),
+            ]
+        })
+        .collect();
+    output::table(&["", "PROFILE", "SERVICES"], &rows);
+    Ok(())
+}
+
+fn activate_profile(client: &WardenClient, name: &str, as_json: bool) -> Result<()> {
+    let response = client.post(&format!("/profile/{}/activate", name), None)?;
+    if as_json {
+        output::json(&response);
+        return Ok(());
+    }
+    println!("{}", output::cell(&response["message"]));
+    for change in ["enabled", "disabled"] {
+        let names = &response[change];
+        if names.as_array().is_some_and(|names| !names.is_empty()) {
+            println!("  {}: {}", change, output::cell(names));
+        }
+    }
+    Ok(())
+}
+
+/// Fields every event has; the rest are the type's details
+const EVENT_COMMON_FIELDS: &[&str] = &["seq", "timestamp", "service", "type"];
+
+fn events(client: &WardenClient, mut args: Vec<String>, as_json: bool) -> Result<()> {
+    let mut query = Vec::new();
+    if let Some(since) = take_option(&mut args, &["--since"])? {
+        query.push(format!("since={}", parse_number::<u64>(&since, "sequence number")?));
+    }
+    if let Some(service) = take_option(&mut args, &["--service"])? {
+        query.push(format!("service={}", service));
+    }
+    if let Some(types) = take_option(&mut args, &["--type"])? {
+        query.push(format!("type={}", types));
+    }
+    if let Some(extra) = args.first() {
+        return Err(usage_error(format!("unexpected argument {}", extra)));
+    }
+
+    let path = if query.is_empty() {
+        "/events".to_string()
+    } else {
+        format!("/events?{}", query.join("&"))
+    };
+    client.stream(&path, |event| {
+        if as_json {
+            println!("{}", event);
+            return Ok(());
+        }
+        let details: Vec<String> = event
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, value)| !EVENT_COMMON_FIELDS.contains(&key.as_str()) && !value.is_null())
+            .map(|(key, value)| format!("{}={}", key, output::cell(value)))
+            .collect();
+        println!(
+            "{:>6}  {}  {:<24}  {:<16}  {}",
+            output::cell(&event["seq"]),
+            output::cell(&event["timestamp"]),
+            output::cell(&event["service"]),
+            output::cell(&event["type"]),
+            details.join(" ")
+        );
+        Ok(())
+    })
+}
+
+fn run(mut args: Vec<String>) -> Result<()> {
+    let url = take_option(&mut args, &["--url"])?;
+    let token = take_option(&mut args, &["--token"])?;
+    let as_json = take_flag(&mut args, &["--json"]);
+    if take_flag(&mut args, &["-h", "--help"]) || args.is_empty() {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let client = WardenClient::new(url, token)?;
+    let command = args.remove(0);
+    match (command.as_str(), args.as_slice()) {
+        ("status", []) => status(&client, as_json),
+        ("services", []) => services(&client, as_json),
+        ("enable" | "disable", [name]) => toggle(&client, &command, name, as_json),
+        ("upgrade", [name]) => upgrade(&client, name, as_json),
+        ("logs", _) => logs(&client, args, as_json),
+        ("port", _) => port(&client, args, as_json),
+        ("reload", []) => reload(&client, as_json),
+        ("profiles", []) => profiles(&client, as_json),
+        ("profile", [action, name]) if action == "activate" => activate_profile(&client, name, as_json),
+        ("events", _) => events(&client, args, as_json),
+        ("enable" | "disable" | "upgrade", _) => Err(usage_error(format!("{} takes one service name", command))),
+        ("profile", _) => Err(usage_error("profile takes `activate <name>`")),
+        ("status" | "services" | "reload" | "profiles", _) => Err(usage_error(format!("{} takes no arguments", command))),
+        _ => Err(usage_error(format!("unknown command {}", command))),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(args) {
+        // The outermost context and the underlying cause; reqwest's middle layers only repeat the URL
+        let cause = e.root_cause().to_string();
+        if e.chain().count() > 1 && cause != e.to_string() {
+            eprintln!("hive-ctl: {}: {}", e, cause);
+        } else {
+            eprintln!("hive-ctl: {}", e);
+        }
+        if e.is::<UsageError>() {
+            eprintln!("Run hive-ctl --help for usage.");
+            process::exit(2);
+        }
+        process::exit(1);
+    }
+}
@@ -4,7 +4,6 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use std::fs;
-use std::env;
 use std::collections::HashMap;
 use tokio::time::{sleep, Duration};
 use chrono::{Local, Utc};
@@ -110,17 +109,6 @@ struct AppState {
     session_start_time: Arc<Mutex<Option<chrono::DateTime<Utc>>>>,
 }
 
-fn get_service_port() -> u16 {
-    env::args()
-        .collect::<Vec<String>>()
-        .windows(2)
-        .find(|w| w[0] == "--port")
-        .and_then(|w| w[1].parse().ok())
-        .or_else(|| env::var("WARDEN_ASSIGNED_PORT").ok()?.parse().ok())
-        .or_else(|| env::var("SERVICE_PORT").ok()?.parse().ok())
-        .unwrap_or(DEFAULT_PORT)
-}
-
 async fn check_for_trigger(image_path: &str, config: &VisualTriggerDetectionConfig) -> (bool, String) {
     // Get the active trigger configuration
     let trigger = match config.triggers.get(&config.active_trigger) {
@@ -387,6 +375,7 @@ async fn status(data: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
         "service": SERVICE_NAME,
         "status": "operational",
+        "version": env!("CARGO_PKG_VERSION"),
         "session_active": session_active,
         "session_directory": session_dir,
     }))
@@ -407,7 +396,7 @@ async fn end_session(data: web::Data<Arc<Mutex<AppState>>>) -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    let port = get_service_port();
+    let port = hive_agent_common::service_port(DEFAULT_PORT);
     println!("🤖 Starting {} on port {}", SERVICE_NAME, port);
     
     // Load config
@@ -3,7 +3,7 @@ use actix_web::{http::header, web, App, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
 
 const SERVICE_NAME: &str = "hive_agent-speech-to-text-generation-loop";
-const SERVICE_PORT: u16 = 6073;
+const DEFAULT_PORT: u16 = 6073;
 
 /// GET /api/v1/hive_agent-speech-to-text-generation-loop/healthcheck/basic - Health check endpoint
 async fn healthcheck() -> impl Responder {
@@ -17,13 +17,14 @@ async fn status() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
         "service": SERVICE_NAME,
         "status": "operational",
-        "version": "0.1.0"
+        "version": env!("CARGO_PKG_VERSION")
     }))
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    println!("🚀 Starting {} on port {}", SERVICE_NAME, SERVICE_PORT);
+    let service_port = hive_agent_common::service_port(DEFAULT_PORT);
+    println!("🚀 Starting {} on port {} (assigned by warden)", SERVICE_NAME, service_port);
 
     HttpServer::new(|| {
         let cors = Cors::default()
@@ -40,7 +41,7 @@ async fn main() -> Result<()> {
             .route("/api/v1/hive_agent-speech-to-text-generation-loop/healthcheck/basic", web::get().to(healthcheck))
             .route("/api/v1/hive_agent-speech-to-text-generation-loop/status", web::get().to(status))
     })
-    .bind(("0.0.0.0", SERVICE_PORT))?
+    .bind(("0.0.0.0", service_port))?
     .run()
     .await?;
 
@@ -0,0 +1,280 @@
+// API authentication - bearer tokens with read or control scope
+use crate::events::{self, EventKind};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderMap},
+        Method,
+    },
+    middleware::Next,
+    HttpMessage, HttpResponse,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::Path, sync::RwLock};
+
+/// Always open so local probes keep working without a token
+const OPEN_PATHS: &[&str] = &["/api/v1/warden/healthcheck/basic"];
+/// Everything else is proxied to a service, which authenticates its own callers
+const WARDEN_API_PREFIX: &str = "/api/v1/warden/";
+
+lazy_static! {
+    static ref API_AUTH: RwLock<ApiAuth> = RwLock::new(ApiAuth::default());
+}
+
+/// What a token may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// GET endpoints only
+    Read,
+    /// Everything, including enabling, disabling, registering and allocating
+    Control,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => f.write_str("read"),
+            Scope::Control => f.write_str("control"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Who the token belongs to; recorded with API actions
+    pub name: String,
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// Contents of the tokens file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TokensFile {
+    tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Default)]
+struct ApiAuth {
+    /// Whether tokens are checked at all; off when no tokens file is configured
+    enforced: bool,
+    tokens: Vec<ApiToken>,
+    /// Require a read token for GET endpoints as well
+    protect_reads: bool,
+}
+
+/// The token a request was authorized with, stored in the request extensions
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub token_name: String,
+}
+
+/// Why a request was refused
+#[derive(Debug)]
+pub enum AuthError {
+    /// No token, or one that is not configured
+    Unauthenticated(String),
+    /// A valid token without the scope the route needs
+    Forbidden(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated(msg) | AuthError::Forbidden(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// Load the tokens file; without one (or with no tokens in it) the API stays open
+///
+/// A tokens file that exists but cannot be read leaves the API closed rather than open.
+pub fn configure(tokens_file: Option<&Path>, protect_reads: bool) {
+    let (enforced, tokens) = match tokens_file.map(load_tokens) {
+        Some(Ok(tokens)) => (!tokens.is_empty(), tokens),
+        Some(Err(e)) => {
+            eprintln!("❌ {}", e);
+            eprintln!("   Refusing every request that needs a token until it is fixed");
+            (true, Vec::new())
+        }
+        None => (false, Vec::new()),
+    };
+
+    if !tokens.is_empty() {
+        println!("🔐 Loaded {} API tokens", tokens.len());
+    } else if !enforced {
+        println!("⚠️  No API tokens configured; the control API is open to anyone who can reach it");
+    }
+    *API_AUTH.write().unwrap() = ApiAuth {
+        enforced,
+        tokens,
+        protect_reads,
+    };
+}
+
+/// Whether requests are checked against tokens at all
+pub fn is_enforced() -> bool {
+    API_AUTH.read().unwrap().enforced
+}
+
+/// Read the tokens file; a missing file means no tokens
+fn load_tokens(path: &Path) -> Result<Vec<ApiToken>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Cannot read API tokens file {}: {}", path.display(), e)),
+    };
+    warn_if_readable_by_others(path);
+
+    let file: TokensFile = serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid API tokens file {}: {}", path.display(), e))?;
+    Ok(file
+        .tokens
+        .into_iter()
+        .filter(|t| {
+            let usable = !t.token.trim().is_empty();
+            if !usable {
+                eprintln!("⚠️  Ignoring API token {} with an empty secret", t.name);
+            }
+            usable
+        })
+        .collect())
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            eprintln!("⚠️  {} is readable by other users; consider chmod 600", path.display());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_path: &Path) {}
+
+/// Compare secrets without returning early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The scope a request needs, or `None` when it is open
+///
+/// Warden tokens only guard the warden's own API; calling a service through
+/// the proxy never needs one.
+fn required_scope(method: &Method, path: &str, protect_reads: bool) -> Option<Scope> {
+    if !path.starts_with(WARDEN_API_PREFIX) || OPEN_PATHS.contains(&path) || method == Method::OPTIONS {
+        return None;
+    }
+    match *method {
+        Method::GET | Method::HEAD if protect_reads => Some(Scope::Read),
+        Method::GET | Method::HEAD => None,
+        _ => Some(Scope::Control),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Check a request against the configured tokens
+fn authorize(req: &ServiceRequest) -> Result<Option<Caller>, AuthError> {
+    let auth = API_AUTH.read().unwrap();
+    if !auth.enforced {
+        return Ok(None);
+    }
+    // The path as the router matches it, with percent-encoded characters decoded
+    let path = req.match_info().as_str();
+    let required = match required_scope(req.method(), path, auth.protect_reads) {
+        Some(scope) => scope,
+        None => return Ok(None),
+    };
+
+    let presented = bearer_token(req.headers()).ok_or_else(|| {
+        AuthError::Unauthenticated("Missing bearer token in the Authorization header".to_string())
+    })?;
+    let token = auth
+        .tokens
+        .iter()
+        .find(|t| constant_time_eq(t.token.as_bytes(), presented.as_bytes()))
+        .ok_or_else(|| AuthError::Unauthenticated("Invalid API token".to_string()))?;
+
+    if token.scope < required {
+        return Err(AuthError::Forbidden(format!(
+            "Token {} has {} scope; this request needs {}",
+            token.name, token.scope, required
+        )));
+    }
+    Ok(Some(Caller {
+        token_name: token.name.clone(),
+    }))
+}
+
+/// Whether a request carries one of the warden's tokens, which the proxy keeps from services
+pub fn carries_warden_token(headers: &HeaderMap) -> bool {
+    bearer_token(headers).is_some_and(|presented| {
+        API_AUTH
+            .read()
+            .unwrap()
+            .tokens
+            .iter()
+            .any(|t| constant_time_eq(t.token.as_bytes(), presented.as_bytes()))
+    })
+}
+
+/// Map a refused request onto an HTTP error response
+pub fn auth_error_response(error: AuthError) -> HttpResponse {
+    let mut response = match &error {
+        AuthError::Unauthenticated(_) => HttpResponse::Unauthorized(),
+        AuthError::Forbidden(_) => HttpResponse::Forbidden(),
+    };
+    if matches!(error, AuthError::Unauthenticated(_)) {
+        response.insert_header((header::WWW_AUTHENTICATE, "Bearer realm=\"hive-warden\""));
+    }
+    response.json(serde_json::json!({
+        "status": "error",
+        "message": error.to_string()
+    }))
+}
+
+/// Middleware: refuse requests without a token of the scope the route needs
+pub async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    match authorize(&req) {
+        Ok(caller) => {
+            if let Some(caller) = caller {
+                req.extensions_mut().insert(caller);
+            }
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        Err(e) => {
+            let response = auth_error_response(e);
+            if req.method() != Method::GET && req.method() != Method::HEAD {
+                // Refused changes belong in the audit trail too
+                let caller = req
+                    .peer_addr()
+                    .map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+                events::emit(
+                    None,
+                    EventKind::Action {
+                        action: format!("{} {}", req.method(), req.path()),
+                        caller,
+                        token: None,
+                        status: response.status().as_u16(),
+                    },
+                );
+            }
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
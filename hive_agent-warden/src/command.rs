@@ -0,0 +1,219 @@
+// Service commands - executable, arguments, environment and working directory for a service
+use crate::{replicas, ServiceConfig, CONFIG_PATH};
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Default executable: a binary named after the service next to the warden
+fn default_executable(service_name: &str) -> PathBuf {
+    let exe_path = env::current_exe().unwrap();
+    let exe_dir = exe_path.parent().unwrap();
+
+    // Service executables are in the same directory as the warden in release mode
+    // Use the service name as-is (Cargo keeps hyphens in binary names)
+    let mut exe_name = service_name.to_string();
+
+    // Add .exe extension on Windows
+    if cfg!(target_os = "windows") {
+        exe_name.push_str(".exe");
+    }
+
+    exe_dir.join(exe_name)
+}
+
+/// Resolve a relative path against the directory holding `core_microservices.json`
+pub fn resolve_against_config(path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        return path;
+    }
+    let config_path = CONFIG_PATH.read().unwrap().clone();
+    match config_path.parent() {
+        Some(base) => base.join(path),
+        None => path,
+    }
+}
+
+/// Expand `${VAR}` references; `$$` produces a literal `$`
+///
+/// Variables are looked up in `vars` first, then in the warden's environment.
+/// An undefined variable is an error rather than an empty string.
+fn interpolate(value: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated ${{ in {:?}", value))?;
+            let name = &after[..end];
+            let resolved = match vars.get(name) {
+                Some(v) => v.clone(),
+                None => env::var(name)
+                    .map_err(|_| anyhow::anyhow!("Undefined variable ${{{}}} in {:?}", name, value))?,
+            };
+            out.push_str(&resolved);
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Variables for `${VAR}` references, and the service's own env with references expanded
+///
+/// Service env may refer to the built-ins and the warden's environment, not to itself.
+/// Replicas see the service's name and their own port and index.
+fn variables(service: &ServiceConfig) -> Result<(BTreeMap<String, String>, BTreeMap<String, String>)> {
+    let port = service.port.to_string();
+    let mut vars: BTreeMap<String, String> = BTreeMap::from([
+        ("SERVICE_NAME".to_string(), replicas::service_of(&service.name).to_string()),
+        ("SERVICE_PORT".to_string(), port.clone()),
+        ("SERVICE_REPLICA".to_string(), replicas::index_of(&service.name).to_string()),
+        ("WARDEN_ASSIGNED_PORT".to_string(), port),
+    ]);
+
+    let mut service_env = BTreeMap::new();
+    for (key, value) in &service.env {
+        service_env.insert(key.clone(), interpolate(value, &vars)?);
+    }
+    for (key, value) in &service_env {
+        vars.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+    Ok((vars, service_env))
+}
+
+/// Bare names are looked up on PATH; anything with a separator is a path
+fn resolve_program(program: &str) -> PathBuf {
+    if Path::new(program).components().count() > 1 {
+        resolve_against_config(program)
+    } else {
+        PathBuf::from(program)
+    }
+}
+
+/// Apply the service's environment and working directory to a command
+fn apply_environment(
+    command: &mut Command,
+    service: &ServiceConfig,
+    vars: &BTreeMap<String, String>,
+    service_env: &BTreeMap<String, String>,
+) -> Result<()> {
+    // The assigned port always wins over the service's own env
+    let port = service.port.to_string();
+    command
+        .envs(service_env)
+        .env("SERVICE_NAME", replicas::service_of(&service.name))
+        .env("SERVICE_PORT", &port)
+        .env("SERVICE_REPLICA", replicas::index_of(&service.name).to_string())
+        .env("WARDEN_ASSIGNED_PORT", &port);
+
+    if let Some(dir) = &service.working_dir {
+        let dir = resolve_against_config(&interpolate(dir, vars)?);
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!("Working directory not found: {}", dir.display()));
+        }
+        command.current_dir(dir);
+    }
+    Ok(())
+}
+
+/// Build the command that runs a service
+///
+/// Without an explicit `command` the service binary next to the warden is run
+/// with `--port` followed by `args`. With one, only `args` are passed. Every
+/// service gets `SERVICE_NAME`, `SERVICE_PORT`, `SERVICE_REPLICA` and
+/// `WARDEN_ASSIGNED_PORT`, which can also be used in `${VAR}` references.
+pub fn build_command(service: &ServiceConfig) -> Result<Command> {
+    let (vars, service_env) = variables(service)?;
+
+    let mut command = match &service.command {
+        Some(program) => Command::new(resolve_program(&interpolate(program, &vars)?)),
+        None => {
+            let exe_path = default_executable(replicas::service_of(&service.name));
+            if !exe_path.exists() {
+                return Err(anyhow::anyhow!("Service executable not found: {}", exe_path.display()));
+            }
+            let mut command = Command::new(exe_path);
+            command.arg("--port").arg(service.port.to_string());
+            command
+        }
+    };
+    for arg in &service.args {
+        command.arg(interpolate(arg, &vars)?);
+    }
+
+    apply_environment(&mut command, service, &vars, &service_env)?;
+    Ok(command)
+}
+
+/// Build a helper command that runs alongside a service, such as an exec health probe
+///
+/// It sees the same variables, environment and working directory as the service.
+pub fn build_helper_command(service: &ServiceConfig, program: &str, args: &[String]) -> Result<Command> {
+    let (vars, service_env) = variables(service)?;
+
+    let mut command = Command::new(resolve_program(&interpolate(program, &vars)?));
+    for arg in args {
+        command.arg(interpolate(arg, &vars)?);
+    }
+
+    apply_environment(&mut command, service, &vars, &service_env)?;
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interpolate;
+    use std::{collections::BTreeMap, env};
+
+    fn vars() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("SERVICE_PORT".to_string(), "6071".to_string()),
+            ("PATH".to_string(), "/opt/service/bin".to_string()),
+        ])
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        assert_eq!(interpolate("--port=${SERVICE_PORT}", &vars()).unwrap(), "--port=6071");
+        assert_eq!(interpolate("${SERVICE_PORT}${SERVICE_PORT}", &vars()).unwrap(), "60716071");
+        assert_eq!(interpolate("no references", &vars()).unwrap(), "no references");
+    }
+
+    #[test]
+    fn the_warden_environment_is_the_fallback() {
+        let package = env::var("CARGO_PKG_NAME").unwrap();
+        assert_eq!(interpolate("${CARGO_PKG_NAME}", &BTreeMap::new()).unwrap(), package);
+        // Built-ins and the service's env win over the warden's
+        assert_eq!(interpolate("${PATH}", &vars()).unwrap(), "/opt/service/bin");
+    }
+
+    #[test]
+    fn undefined_and_unterminated_references_are_errors() {
+        let error = interpolate("${HIVE_WARDEN_TEST_UNDEFINED}", &vars()).unwrap_err();
+        assert!(error.to_string().contains("Undefined variable"), "{}", error);
+        assert!(interpolate("${SERVICE_PORT", &vars()).is_err());
+    }
+
+    #[test]
+    fn dollars_can_be_escaped() {
+        assert_eq!(interpolate("$${SERVICE_PORT}", &vars()).unwrap(), "${SERVICE_PORT}");
+        assert_eq!(interpolate("cost: 5$", &vars()).unwrap(), "cost: 5$");
+        assert_eq!(interpolate("$HOME", &vars()).unwrap(), "$HOME");
+    }
+}
@@ -0,0 +1,86 @@
+// Service discovery - where a service can be reached right now, by name
+use crate::{ports, replicas, WardenState, WARDEN_STATE};
+use serde::Serialize;
+
+/// The current address and health of a service
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceLocation {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub url: String,
+    /// One of `healthy`, `degraded`, `starting`, `unhealthy`, `failed`, `stopped`, `disabled`
+    pub health: &'static str,
+    /// Extra ports the service holds through the allocate endpoint
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub leased_ports: Vec<u16>,
+}
+
+/// The host part of a `Host` header, so callers get back an address they can reach
+pub fn host_from_header(host: &str) -> String {
+    let host = match host.strip_prefix('[') {
+        // IPv6 literal, keep the brackets for use in URLs
+        Some(rest) => rest.split(']').next().map(|h| format!("[{}]", h)),
+        None => host.split(':').next().map(str::to_string),
+    };
+    host.filter(|h| !h.is_empty() && h != "[]")
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+fn locate(state: &WardenState, name: &str, host: &str, leases: &[ports::PortLease]) -> Option<ServiceLocation> {
+    let service = state.services.get(name)?;
+    let runtime = state.runtime.get(name).cloned().unwrap_or_default();
+    // Where it runs now, which differs from the configured port after an upgrade
+    let port = replicas::unit_port(service, 0, leases).unwrap_or(service.port);
+    Some(ServiceLocation {
+        name: service.name.clone(),
+        host: host.to_string(),
+        port,
+        url: format!("http://{}:{}", host, port),
+        health: runtime.health(),
+        leased_ports: leases
+            .iter()
+            .filter(|lease| lease.owner == name)
+            .map(|lease| lease.port)
+            .collect(),
+    })
+}
+
+/// Look up one service by name
+///
+/// Names that are not configured services but hold port leases resolve to their
+/// lowest leased port; the warden does not probe those, so their health is `unknown`.
+pub fn resolve(name: &str, host: &str) -> Option<ServiceLocation> {
+    let leases = ports::leases();
+    if let Some(location) = locate(&WARDEN_STATE.lock().unwrap(), name, host, &leases) {
+        return Some(location);
+    }
+
+    let leased_ports: Vec<u16> = leases
+        .iter()
+        .filter(|lease| lease.owner == name)
+        .map(|lease| lease.port)
+        .collect();
+    let port = *leased_ports.first()?;
+    Some(ServiceLocation {
+        name: name.to_string(),
+        host: host.to_string(),
+        port,
+        url: format!("http://{}:{}", host, port),
+        health: "unknown",
+        leased_ports,
+    })
+}
+
+/// Every configured service, ordered by port
+pub fn catalog(host: &str) -> Vec<ServiceLocation> {
+    let leases = ports::leases();
+    let state = WARDEN_STATE.lock().unwrap();
+    let mut locations: Vec<ServiceLocation> = state
+        .services
+        .keys()
+        .filter_map(|name| locate(&state, name, host, &leases))
+        .collect();
+    locations.sort_by_key(|location| location.port);
+    locations
+}
@@ -0,0 +1,273 @@
+// Event bus - typed lifecycle events, kept in a replay buffer and broadcast to subscribers
+use crate::history;
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+use tokio::sync::broadcast;
+
+/// Events kept in memory for `since` replay
+const EVENT_BUFFER: usize = 1000;
+/// Events a slow subscriber may fall behind before it starts skipping
+const EVENT_SUBSCRIBER_CAPACITY: usize = 256;
+/// Interval between SSE keep-alive comments while nothing happens
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    static ref EVENT_BUS: Mutex<EventBus> = Mutex::new(EventBus::new());
+}
+
+/// What happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A process was spawned for the service
+    Started { pid: u32 },
+    /// A process left running by a previous warden was taken over instead of restarted
+    Adopted { pid: u32 },
+    /// The process exited on its own; `signal` is set when it was killed by one
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    /// The warden stopped the process
+    Stopped,
+    /// The summarised health moved, e.g. from `starting` to `healthy`
+    HealthChanged { from: String, to: String },
+    /// The monitor brought the service back after an exit or failed health checks
+    Restarted,
+    PortAllocated {
+        port: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        requested_port: Option<u16>,
+        reassigned: bool,
+    },
+    ConfigReloaded {
+        added: Vec<String>,
+        removed: Vec<String>,
+        changed: Vec<String>,
+    },
+    /// A profile was activated; `enabled` and `disabled` are the services it switched
+    ProfileActivated {
+        profile: String,
+        enabled: Vec<String>,
+        disabled: Vec<String>,
+    },
+    /// An upgrade moved the service, or one replica of it, to a new process
+    Upgraded {
+        from_port: u16,
+        to_port: u16,
+        pid: u32,
+        /// As reported by the new process
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+    },
+    /// The new processes of an upgrade didn't come up and were stopped; the old ones carry on
+    UpgradeRolledBack { reason: String },
+    /// A change requested through the API; `status` is the HTTP status it was answered with
+    Action {
+        action: String,
+        /// Remote address of the request
+        caller: String,
+        /// Name of the API token used, when tokens are configured
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        status: u16,
+    },
+}
+
+impl EventKind {
+    /// The `type` tag, also used as the SSE event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Started { .. } => "started",
+            EventKind::Adopted { .. } => "adopted",
+            EventKind::Exited { .. } => "exited",
+            EventKind::Stopped => "stopped",
+            EventKind::HealthChanged { .. } => "health_changed",
+            EventKind::Restarted => "restarted",
+            EventKind::PortAllocated { .. } => "port_allocated",
+            EventKind::ConfigReloaded { .. } => "config_reloaded",
+            EventKind::ProfileActivated { .. } => "profile_activated",
+            EventKind::Upgraded { .. } => "upgraded",
+            EventKind::UpgradeRolledBack { .. } => "upgrade_rolled_back",
+            EventKind::Action { .. } => "action",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    /// Increases by one per event; the cursor for `since`
+    pub seq: u64,
+    pub timestamp: String,
+    /// The service the event is about; absent for warden-wide events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+struct EventBus {
+    events: VecDeque<Event>,
+    next_seq: u64,
+    subscribers: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    fn new() -> Self {
+        let (subscribers, _) = broadcast::channel(EVENT_SUBSCRIBER_CAPACITY);
+        Self {
+            events: VecDeque::with_capacity(EVENT_BUFFER),
+            next_seq: 1,
+            subscribers,
+        }
+    }
+}
+
+/// Publish an event about a service, or about the warden when `service` is `None`
+pub fn emit(service: Option<&str>, kind: EventKind) {
+    let mut bus = EVENT_BUS.lock().unwrap();
+    let event = Event {
+        seq: bus.next_seq,
+        timestamp: chrono::Local::now().to_rfc3339(),
+        service: service.map(str::to_string),
+        kind,
+    };
+    bus.next_seq += 1;
+
+    if bus.events.len() == EVENT_BUFFER {
+        bus.events.pop_front();
+    }
+    bus.events.push_back(event.clone());
+    // Written under the bus lock so the file stays in sequence order
+    history::append(&event);
+
+    // No subscribers is the common case, not an error
+    let _ = bus.subscribers.send(event);
+}
+
+/// Continue numbering after the last event recorded by a previous run,
+/// so `since` cursors stay valid across warden restarts
+pub fn resume_after(last_seq: u64) {
+    let mut bus = EVENT_BUS.lock().unwrap();
+    bus.next_seq = bus.next_seq.max(last_seq + 1);
+}
+
+/// Events still in the replay buffer, oldest first
+pub fn recent() -> Vec<Event> {
+    EVENT_BUS.lock().unwrap().events.iter().cloned().collect()
+}
+
+/// Which events a subscriber wants
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    /// Only events about this service
+    pub service: Option<String>,
+    /// Comma-separated event types, e.g. `exited,health_changed`
+    #[serde(rename = "type")]
+    pub types: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        let service = self
+            .service
+            .as_deref()
+            .is_none_or(|name| event.service.as_deref() == Some(name));
+        let kind = self
+            .types
+            .as_deref()
+            .is_none_or(|types| types.split(',').any(|t| t.trim() == event.kind.name()));
+        service && kind
+    }
+}
+
+/// Buffered events after `since`, plus a receiver for everything emitted afterwards
+///
+/// Both are taken under one lock so no event is missed or repeated. Without a
+/// cursor only new events are delivered.
+pub fn subscribe(since: Option<u64>) -> (Vec<Event>, broadcast::Receiver<Event>) {
+    let bus = EVENT_BUS.lock().unwrap();
+    let backlog = match since {
+        Some(since) => bus.events.iter().filter(|e| e.seq > since).cloned().collect(),
+        None => Vec::new(),
+    };
+    (backlog, bus.subscribers.subscribe())
+}
+
+fn sse_event(event: &Event) -> Bytes {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", event.seq, event.kind.name(), data))
+}
+
+/// Server-Sent Events stream of lifecycle events, replaying from `since` when given
+pub fn follow(since: Option<u64>, filter: EventFilter) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (backlog, receiver) = subscribe(since);
+    let backlog: VecDeque<Event> = backlog.into_iter().filter(|e| filter.matches(e)).collect();
+
+    stream::unfold((backlog, receiver, filter), |(mut backlog, mut receiver, filter)| async move {
+        if let Some(event) = backlog.pop_front() {
+            return Some((Ok(sse_event(&event)), (backlog, receiver, filter)));
+        }
+
+        let chunk = loop {
+            match tokio::time::timeout(SSE_KEEPALIVE_INTERVAL, receiver.recv()).await {
+                Ok(Ok(event)) if filter.matches(&event) => break sse_event(&event),
+                Ok(Ok(_)) => continue,
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    break Bytes::from(format!(": skipped {} events\n\n", skipped))
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                // Keeps proxies from closing the connection and notices disconnected clients
+                Err(_) => break Bytes::from_static(b": keep-alive\n\n"),
+            }
+        };
+        Some((Ok(chunk), (backlog, receiver, filter)))
+    })
+}
+
+/// Send lifecycle events over a WebSocket as JSON text messages until the client goes away
+pub async fn forward_to_websocket(
+    mut session: actix_ws::Session,
+    mut messages: actix_ws::MessageStream,
+    since: Option<u64>,
+    filter: EventFilter,
+) {
+    let (backlog, mut receiver) = subscribe(since);
+    for event in backlog.iter().filter(|e| filter.matches(e)) {
+        let text = serde_json::to_string(event).unwrap_or_default();
+        if session.text(text).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => {
+                let text = match received {
+                    Ok(event) if filter.matches(&event) => serde_json::to_string(&event).unwrap_or_default(),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        serde_json::json!({"type": "skipped", "count": skipped}).to_string()
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if session.text(text).await.is_err() {
+                    return;
+                }
+            }
+            message = messages.recv() => match message {
+                Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    let _ = session.close(None).await;
+}
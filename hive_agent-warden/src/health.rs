@@ -0,0 +1,209 @@
+// Health checks - per-service probe strategies and their outcomes
+use crate::{command, ServiceConfig};
+use serde::{Deserialize, Serialize};
+use std::{process::Stdio, time::Duration};
+
+/// How a service's health is probed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum HealthCheck {
+    /// GET a path; healthy when the trimmed body equals `expect`
+    Http {
+        /// Defaults to the service's `health_path`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default = "default_expect")]
+        expect: String,
+    },
+    /// GET a path returning JSON and compare one field against expected values
+    HttpJson {
+        /// Defaults to the service's `health_path`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Dot-separated path to the field, e.g. `status` or `checks.db`
+        #[serde(default = "default_json_field")]
+        field: String,
+        #[serde(default = "default_json_healthy")]
+        healthy: Vec<serde_json::Value>,
+        #[serde(default = "default_json_degraded")]
+        degraded: Vec<serde_json::Value>,
+    },
+    /// Healthy when a TCP connection is accepted
+    Tcp {
+        /// Defaults to the service's port
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+    },
+    /// Run a command; exit code 0 is healthy
+    Exec {
+        command: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        /// Exit code that means degraded rather than unhealthy
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        degraded_exit_code: Option<i32>,
+    },
+}
+
+fn default_expect() -> String {
+    "true".to_string()
+}
+
+fn default_json_field() -> String {
+    "status".to_string()
+}
+
+fn default_json_healthy() -> Vec<serde_json::Value> {
+    vec![serde_json::json!("ok")]
+}
+
+fn default_json_degraded() -> Vec<serde_json::Value> {
+    vec![serde_json::json!("degraded")]
+}
+
+/// Outcome of a single probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    /// Up and answering, but reporting reduced functionality
+    Degraded,
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Whether the service answered well enough to count as up
+    pub fn is_up(self) -> bool {
+        self != HealthStatus::Unhealthy
+    }
+}
+
+/// Probe a service with its configured check; a plain HTTP check on `health_path` by default
+pub async fn probe(service: &ServiceConfig) -> HealthStatus {
+    let timeout = Duration::from_millis(service.healthcheck_timeout_millisecs);
+    match &service.healthcheck {
+        None => http_plain(service, None, "true", timeout).await,
+        Some(HealthCheck::Http { path, expect }) => {
+            http_plain(service, path.as_deref(), expect, timeout).await
+        }
+        Some(HealthCheck::HttpJson { path, field, healthy, degraded }) => {
+            http_json(service, path.as_deref(), field, healthy, degraded, timeout).await
+        }
+        Some(HealthCheck::Tcp { port }) => tcp(port.unwrap_or(service.port), timeout).await,
+        Some(HealthCheck::Exec { command, args, degraded_exit_code }) => {
+            exec(service, command, args, *degraded_exit_code, timeout).await
+        }
+    }
+}
+
+/// Ask a running service which version it is, on its `version_path`
+///
+/// A JSON object answer is read from its `version` field; any other answer is
+/// the version itself.
+pub async fn version(service: &ServiceConfig) -> Option<String> {
+    let path = service.version_path.as_deref()?;
+    let timeout = Duration::from_millis(service.healthcheck_timeout_millisecs);
+    let resp = http_get(&health_url(service, Some(path)), timeout)
+        .await
+        .filter(|resp| resp.status().is_success())?;
+    let text = resp.text().await.ok()?;
+    let version = match serde_json::from_str(&text) {
+        Ok(serde_json::Value::Object(body)) => body.get("version")?.as_str()?.to_string(),
+        _ => text.trim().to_string(),
+    };
+    (!version.is_empty()).then_some(version)
+}
+
+fn health_url(service: &ServiceConfig, path: Option<&str>) -> String {
+    let path = path.unwrap_or(&service.health_path);
+    format!("http://127.0.0.1:{}/{}", service.port, path.trim_start_matches('/'))
+}
+
+async fn http_get(url: &str, timeout: Duration) -> Option<reqwest::Response> {
+    let client = reqwest::Client::builder().timeout(timeout).build().ok()?;
+    client.get(url).send().await.ok()
+}
+
+async fn http_plain(service: &ServiceConfig, path: Option<&str>, expect: &str, timeout: Duration) -> HealthStatus {
+    let body = match http_get(&health_url(service, path), timeout).await {
+        Some(resp) => resp.text().await.ok(),
+        None => None,
+    };
+    match body {
+        Some(text) if text.trim() == expect => HealthStatus::Healthy,
+        _ => HealthStatus::Unhealthy,
+    }
+}
+
+async fn http_json(
+    service: &ServiceConfig,
+    path: Option<&str>,
+    field: &str,
+    healthy: &[serde_json::Value],
+    degraded: &[serde_json::Value],
+    timeout: Duration,
+) -> HealthStatus {
+    let resp = match http_get(&health_url(service, path), timeout).await {
+        Some(resp) if resp.status().is_success() => resp,
+        _ => return HealthStatus::Unhealthy,
+    };
+    let body: serde_json::Value = match resp.text().await.map(|text| serde_json::from_str(&text)) {
+        Ok(Ok(body)) => body,
+        _ => return HealthStatus::Unhealthy,
+    };
+
+    let value = field
+        .split('.')
+        .try_fold(&body, |value, key| value.get(key));
+    match value {
+        Some(v) if healthy.contains(v) => HealthStatus::Healthy,
+        Some(v) if degraded.contains(v) => HealthStatus::Degraded,
+        _ => HealthStatus::Unhealthy,
+    }
+}
+
+async fn tcp(port: u16, timeout: Duration) -> HealthStatus {
+    let connect = tokio::net::TcpStream::connect(("127.0.0.1", port));
+    match tokio::time::timeout(timeout, connect).await {
+        Ok(Ok(_)) => HealthStatus::Healthy,
+        _ => HealthStatus::Unhealthy,
+    }
+}
+
+async fn exec(
+    service: &ServiceConfig,
+    program: &str,
+    args: &[String],
+    degraded_exit_code: Option<i32>,
+    timeout: Duration,
+) -> HealthStatus {
+    let mut command = match command::build_helper_command(service, program, args) {
+        Ok(command) => tokio::process::Command::from(command),
+        Err(e) => {
+            eprintln!("⚠️  Health probe for {} not runnable: {}", service.name, e);
+            return HealthStatus::Unhealthy;
+        }
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    let status = match command.spawn() {
+        Ok(mut child) => match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(Ok(status)) => status,
+            // Timed out; dropping the child kills it
+            _ => return HealthStatus::Unhealthy,
+        },
+        Err(e) => {
+            eprintln!("⚠️  Health probe for {} failed to run: {}", service.name, e);
+            return HealthStatus::Unhealthy;
+        }
+    };
+
+    match status.code() {
+        Some(0) => HealthStatus::Healthy,
+        code if code.is_some() && code == degraded_exit_code => HealthStatus::Degraded,
+        _ => HealthStatus::Unhealthy,
+    }
+}
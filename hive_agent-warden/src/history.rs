@@ -0,0 +1,232 @@
+// Event history - every lifecycle event and API action, appended to a file for later questions
+use crate::{
+    events::{self, Event, EventFilter},
+    settings::HistorySettings,
+};
+use chrono::{DateTime, FixedOffset};
+use lazy_static::lazy_static;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+lazy_static! {
+    static ref HISTORY: Mutex<History> = Mutex::new(History::default());
+}
+
+#[derive(Default)]
+struct History {
+    /// `None` until the warden has loaded its settings, or when history is turned off
+    settings: Option<HistorySettings>,
+    active: Option<ActiveFile>,
+}
+
+struct ActiveFile {
+    file: File,
+    bytes: u64,
+}
+
+/// What to look up in the history
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub filter: EventFilter,
+    pub from: Option<DateTime<FixedOffset>>,
+    pub to: Option<DateTime<FixedOffset>>,
+    /// Keep only the most recent matches
+    pub limit: usize,
+}
+
+impl HistoryQuery {
+    fn matches(&self, event: &Event) -> bool {
+        if !self.filter.matches(event) {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        let at = match DateTime::parse_from_rfc3339(&event.timestamp) {
+            Ok(at) => at,
+            Err(_) => return false,
+        };
+        self.from.is_none_or(|from| at >= from) && self.to.is_none_or(|to| at <= to)
+    }
+}
+
+/// `history.jsonl.1` is the newest archive, `history.jsonl.N` the oldest
+fn archive_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Every history file, oldest first
+fn files_oldest_first(settings: &HistorySettings, path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = (1..=settings.max_archives)
+        .rev()
+        .map(|index| archive_path(path, index))
+        .filter(|p| p.exists())
+        .collect();
+    if path.exists() {
+        files.push(path.to_path_buf());
+    }
+    files
+}
+
+/// Pass every event in a file to `each`; returns how many lines could not be parsed
+///
+/// A line cut short by a crash is skipped, but a failing read is an error
+/// rather than the end of the file.
+fn read_events(path: &Path, mut each: impl FnMut(Event)) -> io::Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut skipped = 0;
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_slice(&line) {
+            Ok(event) => each(event),
+            Err(_) => skipped += 1,
+        }
+    }
+    Ok(skipped)
+}
+
+/// Enable the history file and carry on numbering from the last recorded event
+pub fn configure(settings: HistorySettings) {
+    let path = match &settings.file {
+        Some(path) => path.clone(),
+        None => {
+            *HISTORY.lock().unwrap() = History::default();
+            return;
+        }
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("⚠️  Failed to create history directory {}: {}", dir.display(), e);
+        }
+    }
+
+    let last_seq = files_oldest_first(&settings, &path).iter().rev().find_map(|file| {
+        let mut last_seq = None;
+        match read_events(file, |event| last_seq = last_seq.max(Some(event.seq))) {
+            Ok(0) => {}
+            Ok(skipped) => eprintln!("⚠️  Skipping {} unreadable lines of {}", skipped, file.display()),
+            Err(e) => eprintln!("⚠️  Failed to read history file {}: {}", file.display(), e),
+        }
+        last_seq
+    });
+    if let Some(last_seq) = last_seq {
+        events::resume_after(last_seq);
+    }
+
+    let mut history = HISTORY.lock().unwrap();
+    history.active = None;
+    history.settings = Some(settings);
+}
+
+/// Shift archives up by one and move the active file to `.1`, dropping the oldest
+fn rotate(settings: &HistorySettings, path: &Path) -> io::Result<()> {
+    if settings.max_archives == 0 {
+        return fs::remove_file(path);
+    }
+    let oldest = archive_path(path, settings.max_archives);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for index in (1..settings.max_archives).rev() {
+        let from = archive_path(path, index);
+        if from.exists() {
+            fs::rename(&from, archive_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, archive_path(path, 1))
+}
+
+fn open_active(path: &Path) -> io::Result<ActiveFile> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let bytes = file.metadata()?.len();
+    Ok(ActiveFile { file, bytes })
+}
+
+/// Append one event to the history file
+pub fn append(event: &Event) {
+    let mut history = HISTORY.lock().unwrap();
+    let settings = match &history.settings {
+        Some(settings) => settings.clone(),
+        None => return,
+    };
+    let path = match &settings.file {
+        Some(path) => path,
+        None => return,
+    };
+
+    if history.active.as_ref().is_some_and(|active| active.bytes >= settings.max_file_bytes) {
+        history.active = None;
+        if let Err(e) = rotate(&settings, path) {
+            eprintln!("⚠️  Failed to rotate history file {}: {}", path.display(), e);
+        }
+    }
+    if history.active.is_none() {
+        match open_active(path) {
+            Ok(active) => history.active = Some(active),
+            Err(e) => {
+                eprintln!("⚠️  Failed to open history file {}: {}", path.display(), e);
+                return;
+            }
+        }
+    }
+
+    let mut line = serde_json::to_string(event).unwrap_or_default();
+    line.push('\n');
+    let active = history.active.as_mut().expect("opened above");
+    match active.file.write_all(line.as_bytes()) {
+        Ok(()) => active.bytes += line.len() as u64,
+        Err(e) => {
+            eprintln!("⚠️  Failed to write history file {}: {}", path.display(), e);
+            history.active = None;
+        }
+    }
+}
+
+/// Matching events, oldest first, keeping only the last `limit`
+///
+/// Without a history file only the events still in memory are searched. A
+/// file that can't be read fails the query rather than cutting its results short.
+pub fn query(query: &HistoryQuery) -> io::Result<Vec<Event>> {
+    if query.limit == 0 {
+        return Ok(Vec::new());
+    }
+    let mut matches = VecDeque::with_capacity(query.limit.min(1024));
+    let mut keep = |event: Event| {
+        if query.matches(&event) {
+            if matches.len() == query.limit {
+                matches.pop_front();
+            }
+            matches.push_back(event);
+        }
+    };
+
+    let settings = HISTORY.lock().unwrap().settings.clone();
+    match settings.as_ref().and_then(|s| s.file.as_deref().map(|path| (s, path))) {
+        Some((settings, path)) => {
+            let mut skipped = 0;
+            for file in files_oldest_first(settings, path) {
+                match read_events(&file, &mut keep) {
+                    Ok(count) => skipped += count,
+                    // Rotated away since the listing
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if skipped > 0 {
+                eprintln!("⚠️  Skipped {} unreadable history lines", skipped);
+            }
+        }
+        None => events::recent().into_iter().for_each(keep),
+    }
+    Ok(matches.into())
+}
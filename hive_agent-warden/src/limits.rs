@@ -0,0 +1,384 @@
+// Resource limits - memory, CPU and file caps applied to a service when it is spawned
+//
+// On Linux with cgroups v2 each limited service gets its own cgroup under the
+// warden's; otherwise the limits fall back to rlimits and sched_setaffinity
+// set in the child just before exec. The cgroups are only set up once a
+// service with memory or CPU limits is started.
+use crate::settings::LimitSettings;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{io, path::PathBuf, process::Command, sync::Mutex};
+
+lazy_static! {
+    static ref CGROUPS: Mutex<Cgroups> = Mutex::new(Cgroups::default());
+}
+
+#[derive(Default)]
+struct Cgroups {
+    /// `None` until the warden has loaded its settings
+    settings: Option<LimitSettings>,
+    /// The cgroup services are placed under: `None` until the first limited service
+    /// is started, then `Some(None)` when cgroups v2 can't be used
+    base: Option<Option<PathBuf>>,
+}
+
+/// Optional per-service limits from `core_microservices.json`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Memory cap in bytes; without cgroups this caps the address space instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_max_bytes: Option<u64>,
+    /// CPU time as a percentage of one core, e.g. 150 for one and a half cores; needs cgroups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_quota_percent: Option<u32>,
+    /// Cores the service may run on
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assigned_cores: Vec<usize>,
+    /// Scheduling priority, -20 (highest) to 19 (lowest)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a running service is using right now
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    pub memory_bytes: u64,
+    /// CPU time consumed since the service started
+    pub cpu_time_usec: u64,
+    /// `cgroup` for the whole cgroup, `process` for the main process only
+    pub source: &'static str,
+}
+
+/// Keep the settings for when the first service with limits is started
+///
+/// Nothing about the warden's own cgroup changes until then.
+pub fn configure(settings: &LimitSettings) {
+    *CGROUPS.lock().unwrap() = Cgroups {
+        settings: Some(settings.clone()),
+        base: None,
+    };
+}
+
+/// Where service cgroups go, delegating the memory and cpu controllers to them the first time
+#[cfg(target_os = "linux")]
+fn prepare_base() -> Option<PathBuf> {
+    let mut cgroups = CGROUPS.lock().unwrap();
+    if let Some(base) = &cgroups.base {
+        return base.clone();
+    }
+    let settings = cgroups.settings.clone().unwrap_or_default();
+    let base = if settings.cgroups {
+        cgroup::prepare(settings.cgroup_root.as_deref())
+    } else {
+        None
+    };
+    match &base {
+        Some(base) => println!("🧱 Applying resource limits through cgroups in {}", base.display()),
+        None if settings.cgroups && cgroup::supported() => {
+            println!("⚠️  cgroups v2 unavailable; resource limits fall back to rlimits")
+        }
+        None => {}
+    }
+    cgroups.base = Some(base.clone());
+    base
+}
+
+/// The cgroup services are placed under, if one has been set up
+fn base() -> Option<PathBuf> {
+    CGROUPS.lock().unwrap().base.clone().flatten()
+}
+
+fn service_cgroup(cgroup: &str) -> Option<PathBuf> {
+    base().map(|base| base.join(cgroup))
+}
+
+/// The two cgroups a unit's processes take turns in: one named after it, and one
+/// for the new process of an upgrade; ':' is never part of a unit name
+fn unit_cgroups(unit: &str) -> [String; 2] {
+    [unit.to_string(), format!("{}:upgrade", unit)]
+}
+
+/// Which of a unit's cgroups its process `pid` is in
+fn current_cgroup(unit: &str, pid: u32) -> Option<String> {
+    let base = base()?;
+    let dir = cgroup::of_process(pid)?;
+    unit_cgroups(unit).into_iter().find(|name| base.join(name) == dir)
+}
+
+/// The cgroup for a new process started beside a unit's running `pid`
+///
+/// It is whichever of the unit's cgroups the running process is not in, so
+/// during an upgrade each process has the unit's limits to itself.
+pub fn staging_cgroup(unit: &str, pid: u32) -> String {
+    let [own, staging] = unit_cgroups(unit);
+    if current_cgroup(unit, pid).is_some_and(|current| current == staging) {
+        own
+    } else {
+        staging
+    }
+}
+
+/// Set up a service's limits so they take effect in the child before it execs
+///
+/// A limited child is placed in the cgroup named `cgroup`.
+pub fn apply(cgroup: &str, limits: &ResourceLimits, command: &mut Command) -> io::Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    platform::apply(cgroup, limits, command)
+}
+
+/// Remove the cgroups of a unit's stopped processes
+pub fn release(unit: &str) {
+    for cgroup in unit_cgroups(unit) {
+        if let Some(dir) = service_cgroup(&cgroup) {
+            // Only empty cgroups can be removed; a running process keeps its own
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Current usage of a unit, from the cgroup of its process `pid` if it has one, else from that process
+pub fn usage(unit: &str, pid: u32) -> Option<ResourceUsage> {
+    let cgroup = current_cgroup(unit, pid).and_then(|cgroup| service_cgroup(&cgroup));
+    platform::usage(cgroup.as_deref(), pid)
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use std::{
+        fs, io,
+        path::{Path, PathBuf},
+    };
+
+    const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+    /// Where the warden moves itself so its own cgroup can hand controllers to services
+    const WARDEN_LEAF: &str = "hive_agent-warden";
+
+    /// A unified (v2) hierarchy is mounted
+    pub fn supported() -> bool {
+        Path::new(CGROUP_MOUNT).join("cgroup.controllers").exists()
+    }
+
+    /// The cgroup of a process, from the `0::` line of /proc/{pid}/cgroup
+    fn cgroup_of(proc_dir: &str) -> Option<PathBuf> {
+        let contents = fs::read_to_string(format!("/proc/{}/cgroup", proc_dir)).ok()?;
+        let path = contents.lines().find_map(|line| line.strip_prefix("0::"))?;
+        Some(Path::new(CGROUP_MOUNT).join(path.trim_start_matches('/')))
+    }
+
+    /// The warden's own cgroup
+    fn own_cgroup() -> Option<PathBuf> {
+        cgroup_of("self")
+    }
+
+    pub fn of_process(pid: u32) -> Option<PathBuf> {
+        cgroup_of(&pid.to_string())
+    }
+
+    fn has_processes(dir: &Path) -> bool {
+        fs::read_to_string(dir.join("cgroup.procs")).is_ok_and(|procs| !procs.trim().is_empty())
+    }
+
+    fn enable_controllers(base: &Path) -> io::Result<()> {
+        let available = fs::read_to_string(base.join("cgroup.controllers"))?;
+        for controller in ["memory", "cpu"] {
+            if !available.split_whitespace().any(|c| c == controller) {
+                return Err(io::Error::other(format!("the {} controller is not delegated", controller)));
+            }
+        }
+        fs::write(base.join("cgroup.subtree_control"), "+memory +cpu")
+    }
+
+    /// Make `root` (or the warden's own cgroup) able to hold service cgroups
+    pub fn prepare(root: Option<&Path>) -> Option<PathBuf> {
+        if !supported() {
+            return None;
+        }
+        let base = match root {
+            Some(root) => root.to_path_buf(),
+            None => own_cgroup()?,
+        };
+        let result = fs::create_dir_all(&base).and_then(|_| {
+            // A cgroup with processes in it can't delegate controllers, so the
+            // warden moves into a leaf of its own first
+            if has_processes(&base) {
+                let leaf = base.join(WARDEN_LEAF);
+                fs::create_dir_all(&leaf)?;
+                fs::write(leaf.join("cgroup.procs"), std::process::id().to_string())?;
+            }
+            enable_controllers(&base)
+        });
+        match result {
+            Ok(()) => Some(base),
+            Err(e) => {
+                eprintln!("⚠️  Cannot use cgroup {}: {}", base.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Create a service's cgroup with its limits; returns the `cgroup.procs` file to join
+    pub fn create(dir: &Path, memory_max: Option<u64>, cpu_quota_percent: Option<u32>) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let memory = memory_max.map_or_else(|| "max".to_string(), |bytes| bytes.to_string());
+        fs::write(dir.join("memory.max"), memory)?;
+        // Quota per 100ms period
+        let cpu = cpu_quota_percent.map_or_else(
+            || "max 100000".to_string(),
+            |percent| format!("{} 100000", u64::from(percent.max(1)) * 1000),
+        );
+        fs::write(dir.join("cpu.max"), cpu)?;
+        Ok(dir.join("cgroup.procs"))
+    }
+
+    /// Memory in use and CPU time consumed by everything in a cgroup
+    pub fn usage(dir: &Path) -> Option<(u64, u64)> {
+        let memory = fs::read_to_string(dir.join("memory.current")).ok()?.trim().parse().ok()?;
+        let cpu_stat = fs::read_to_string(dir.join("cpu.stat")).ok()?;
+        let cpu = cpu_stat
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))?
+            .trim()
+            .parse()
+            .ok()?;
+        Some((memory, cpu))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{cgroup, prepare_base, ResourceLimits, ResourceUsage};
+    use std::{
+        ffi::CString, fs, io, os::unix::ffi::OsStrExt, os::unix::process::CommandExt, path::Path, process::Command,
+    };
+
+    pub fn apply(cgroup_name: &str, limits: &ResourceLimits, command: &mut Command) -> io::Result<()> {
+        let mut memory_rlimit = limits.memory_max_bytes;
+        let mut cgroup_procs = None;
+        let wants_cgroup = limits.memory_max_bytes.is_some() || limits.cpu_quota_percent.is_some();
+        match wants_cgroup.then(prepare_base).flatten().map(|base| base.join(cgroup_name)) {
+            Some(dir) => {
+                let procs = cgroup::create(&dir, limits.memory_max_bytes, limits.cpu_quota_percent)?;
+                cgroup_procs = Some(CString::new(procs.as_os_str().as_bytes())?);
+                memory_rlimit = None;
+            }
+            None if limits.cpu_quota_percent.is_some() => {
+                eprintln!("⚠️  {}: cpu_quota_percent needs cgroups v2 and is not enforced", cgroup_name);
+            }
+            None => {}
+        }
+
+        if let Some(core) = limits.assigned_cores.iter().find(|&&core| core >= libc::CPU_SETSIZE as usize) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no such core {}", core)));
+        }
+        let cores = limits.assigned_cores.clone();
+        let nice = limits.nice;
+        let max_open_files = limits.max_open_files;
+        // SAFETY: the closure runs between fork and exec and only makes raw
+        // syscalls on values prepared beforehand; it does not allocate
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(procs) = &cgroup_procs {
+                    // Writing 0 moves the writing process
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 || libc::write(fd, b"0".as_ptr().cast(), 1) != 1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    libc::close(fd);
+                }
+                if !cores.is_empty() {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+                    for &core in &cores {
+                        libc::CPU_SET(core, &mut set);
+                    }
+                    if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(files) = max_open_files {
+                    if libc::setrlimit(libc::RLIMIT_NOFILE, &rlimit(files)) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(bytes) = memory_rlimit {
+                    if libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes)) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Same soft and hard limit
+    fn rlimit(value: u64) -> libc::rlimit {
+        libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        }
+    }
+
+    pub fn usage(cgroup: Option<&Path>, pid: u32) -> Option<ResourceUsage> {
+        if let Some((memory_bytes, cpu_time_usec)) = cgroup.and_then(cgroup::usage) {
+            return Some(ResourceUsage {
+                memory_bytes,
+                cpu_time_usec,
+                source: "cgroup",
+            });
+        }
+
+        // SAFETY: sysconf has no preconditions
+        let (page_size, ticks) = unsafe { (libc::sysconf(libc::_SC_PAGESIZE), libc::sysconf(libc::_SC_CLK_TCK)) };
+        let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+        let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        // utime and stime, fields 14 and 15 of stat(5)
+        let cpu_ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+        Some(ResourceUsage {
+            memory_bytes: resident_pages * page_size.max(1) as u64,
+            cpu_time_usec: cpu_ticks * 1_000_000 / ticks.max(1) as u64,
+            source: "process",
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod cgroup {
+    use std::path::PathBuf;
+
+    pub fn of_process(_pid: u32) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::{ResourceLimits, ResourceUsage};
+    use std::{io, path::Path, process::Command};
+
+    pub fn apply(cgroup_name: &str, _limits: &ResourceLimits, _command: &mut Command) -> io::Result<()> {
+        eprintln!("⚠️  {}: resource limits are only enforced on Linux", cgroup_name);
+        Ok(())
+    }
+
+    pub fn usage(_cgroup: Option<&Path>, _pid: u32) -> Option<ResourceUsage> {
+        None
+    }
+}
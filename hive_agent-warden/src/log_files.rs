@@ -0,0 +1,290 @@
+// Persistent service logs - per-service files with rotation, compression and retention
+use crate::logs::LogLine;
+use crate::settings::LogSettings;
+use flate2::{write::GzEncoder, Compression};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+lazy_static! {
+    static ref LOG_FILES: Mutex<LogFiles> = Mutex::new(LogFiles::default());
+}
+
+#[derive(Default)]
+struct LogFiles {
+    /// `None` until the warden has loaded its settings; lines are then only kept in memory
+    settings: Option<LogSettings>,
+    active: HashMap<String, ActiveLog>,
+}
+
+struct ActiveLog {
+    file: File,
+    bytes: u64,
+    opened: SystemTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogSegment {
+    pub file: String,
+    pub size_bytes: u64,
+    pub modified: String,
+    /// The file currently being written to
+    pub active: bool,
+    pub compressed: bool,
+}
+
+/// Enable file logging with the given settings
+///
+/// Segments left beyond the retention limits by a previous run are removed
+/// now; otherwise that only happens when a service's log next rotates.
+pub fn configure(settings: LogSettings) -> io::Result<()> {
+    fs::create_dir_all(&settings.directory)?;
+    for entry in fs::read_dir(&settings.directory)?.filter_map(|entry| entry.ok()) {
+        if let Some(service_name) = entry.file_name().to_str().filter(|_| entry.path().is_dir()) {
+            enforce_retention(&settings, service_name);
+        }
+    }
+    let mut files = LOG_FILES.lock().unwrap();
+    files.active.clear();
+    files.settings = Some(settings);
+    Ok(())
+}
+
+fn service_dir(settings: &LogSettings, service_name: &str) -> PathBuf {
+    settings.directory.join(service_name)
+}
+
+fn active_file_name(service_name: &str) -> String {
+    format!("{}.log", service_name)
+}
+
+/// Append a captured line to the service's active log file
+pub fn write(service_name: &str, line: &LogLine) {
+    let mut files = LOG_FILES.lock().unwrap();
+    let settings = match &files.settings {
+        Some(settings) => settings.clone(),
+        None => return,
+    };
+
+    if let Some(active) = files.active.get(service_name) {
+        let age = active.opened.elapsed().unwrap_or_default();
+        if active.bytes >= settings.max_file_bytes
+            || age >= Duration::from_secs(settings.max_file_age_secs)
+        {
+            files.active.remove(service_name);
+            if let Err(e) = rotate(&settings, service_name) {
+                eprintln!("⚠️  Failed to rotate log for {}: {}", service_name, e);
+            }
+        }
+    }
+
+    if !files.active.contains_key(service_name) {
+        match open_active(&settings, service_name) {
+            Ok(active) => {
+                files.active.insert(service_name.to_string(), active);
+            }
+            Err(e) => {
+                eprintln!("⚠️  Failed to open log file for {}: {}", service_name, e);
+                return;
+            }
+        }
+    }
+
+    let formatted = format!("{} [{}] {}\n", line.timestamp, line.stream.as_str(), line.line);
+    if let Some(active) = files.active.get_mut(service_name) {
+        match active.file.write_all(formatted.as_bytes()) {
+            Ok(()) => active.bytes += formatted.len() as u64,
+            Err(e) => {
+                eprintln!("⚠️  Failed to write log for {}: {}", service_name, e);
+                files.active.remove(service_name);
+            }
+        }
+    }
+}
+
+/// Open (or continue) the active log file, picking up its size and age after a restart
+fn open_active(settings: &LogSettings, service_name: &str) -> io::Result<ActiveLog> {
+    let dir = service_dir(settings, service_name);
+    fs::create_dir_all(&dir)?;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(active_file_name(service_name)))?;
+    let metadata = file.metadata()?;
+    let opened = metadata
+        .created()
+        .or_else(|_| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+
+    Ok(ActiveLog {
+        file,
+        bytes: metadata.len(),
+        opened,
+    })
+}
+
+/// Move the active file aside as a timestamped segment
+///
+/// Compression and retention run in the background so the pipe reader isn't held up.
+fn rotate(settings: &LogSettings, service_name: &str) -> io::Result<()> {
+    let dir = service_dir(settings, service_name);
+    let active = dir.join(active_file_name(service_name));
+    if !active.exists() {
+        return Ok(());
+    }
+
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut segment = dir.join(format!("{}-{}.log", service_name, stamp));
+    let mut suffix = 1;
+    while segment.exists() || gz_path(&segment).exists() {
+        segment = dir.join(format!("{}-{}-{}.log", service_name, stamp, suffix));
+        suffix += 1;
+    }
+    fs::rename(&active, &segment)?;
+
+    let settings = settings.clone();
+    let service_name = service_name.to_string();
+    thread::spawn(move || {
+        if settings.compress {
+            if let Err(e) = compress_segment(&segment) {
+                eprintln!("⚠️  Failed to compress {}: {}", segment.display(), e);
+            }
+        }
+        enforce_retention(&settings, &service_name);
+    });
+
+    Ok(())
+}
+
+fn gz_path(segment: &Path) -> PathBuf {
+    let mut name = segment.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Gzip a rotated segment and remove the uncompressed copy
+fn compress_segment(segment: &Path) -> io::Result<()> {
+    let data = fs::read(segment)?;
+    let mut encoder = GzEncoder::new(File::create(gz_path(segment))?, Compression::default());
+    encoder.write_all(&data)?;
+    encoder.finish()?;
+    fs::remove_file(segment)
+}
+
+/// Delete archived segments beyond the count and age limits, oldest first
+fn enforce_retention(settings: &LogSettings, service_name: &str) {
+    let max_age = Duration::from_secs(settings.max_archive_age_days * 24 * 60 * 60);
+    let mut archives: Vec<(PathBuf, SystemTime)> = archived_files(settings, service_name)
+        .into_iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect();
+    archives.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+
+    for (index, (path, modified)) in archives.iter().enumerate() {
+        let expired = modified.elapsed().unwrap_or_default() > max_age;
+        if index >= settings.max_archives || expired {
+            if let Err(e) = fs::remove_file(path) {
+                eprintln!("⚠️  Failed to remove old log {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Every segment file for a service except the active one
+fn archived_files(settings: &LogSettings, service_name: &str) -> Vec<PathBuf> {
+    let active = active_file_name(service_name);
+    match fs::read_dir(service_dir(settings, service_name)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.file_name().is_some_and(|n| n != active.as_str()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Active file plus archived segments for a service, newest first
+pub fn list_segments(service_name: &str) -> Vec<LogSegment> {
+    let settings = match &LOG_FILES.lock().unwrap().settings {
+        Some(settings) => settings.clone(),
+        None => return Vec::new(),
+    };
+
+    let dir = service_dir(&settings, service_name);
+    let active_name = active_file_name(service_name);
+    let mut paths = archived_files(&settings, service_name);
+    paths.push(dir.join(&active_name));
+
+    let mut segments: Vec<(SystemTime, LogSegment)> = paths
+        .into_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            let modified = metadata.modified().ok()?;
+            let file = path.file_name()?.to_string_lossy().into_owned();
+            Some((modified, LogSegment {
+                active: file == active_name,
+                compressed: file.ends_with(".gz"),
+                size_bytes: metadata.len(),
+                modified: chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339(),
+                file,
+            }))
+        })
+        .collect();
+    segments.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    segments.into_iter().map(|(_, segment)| segment).collect()
+}
+
+/// A single path component that names a file, with no way out of its directory
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+        && Path::new(name).file_name() == Some(OsStr::new(name))
+}
+
+/// Path of a listed segment; anything that isn't one of the service's segments is rejected
+pub fn segment_path(service_name: &str, file: &str) -> Option<PathBuf> {
+    if !is_plain_name(service_name) || !is_plain_name(file) {
+        return None;
+    }
+    let settings = LOG_FILES.lock().unwrap().settings.clone()?;
+    let dir = service_dir(&settings, service_name);
+    let segment = list_segments(service_name).into_iter().find(|segment| segment.file == file)?;
+
+    // A symlink in the log directory mustn't lead anywhere else either
+    let path = dir.join(&segment.file).canonicalize().ok()?;
+    path.starts_with(dir.canonicalize().ok()?).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_plain_name;
+
+    #[test]
+    fn plain_names_are_accepted() {
+        assert!(is_plain_name("rag"));
+        assert!(is_plain_name("rag-20240101-120000.log.gz"));
+        assert!(is_plain_name(".hidden"));
+    }
+
+    #[test]
+    fn names_that_leave_the_directory_are_rejected() {
+        for name in ["", ".", "..", "../rag", "a/b", "a\\b", "/etc/passwd", "rag/.."] {
+            assert!(!is_plain_name(name), "{:?} should be rejected", name);
+        }
+    }
+}
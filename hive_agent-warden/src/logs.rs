@@ -0,0 +1,177 @@
+// Per-service log capture - drains child stdout/stderr into bounded ring buffers
+use crate::log_files;
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Read},
+    process::Child,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+use tokio::sync::broadcast;
+
+/// Lines kept in memory per service
+const LOG_BUFFER_LINES: usize = 1000;
+/// Lines a slow follower may fall behind before it starts skipping
+const LOG_FOLLOW_CAPACITY: usize = 256;
+/// Interval between SSE keep-alive comments while a service is quiet
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    static ref SERVICE_LOGS: Mutex<HashMap<String, ServiceLog>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub seq: u64,
+    pub timestamp: String,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+struct ServiceLog {
+    lines: VecDeque<LogLine>,
+    next_seq: u64,
+    followers: broadcast::Sender<LogLine>,
+}
+
+impl ServiceLog {
+    fn new() -> Self {
+        let (followers, _) = broadcast::channel(LOG_FOLLOW_CAPACITY);
+        Self {
+            lines: VecDeque::with_capacity(LOG_BUFFER_LINES),
+            next_seq: 0,
+            followers,
+        }
+    }
+}
+
+/// Take a freshly spawned child's stdout/stderr pipes and drain them in the background
+pub fn capture_output(service_name: &str, child: &mut Child) {
+    if let Some(stdout) = child.stdout.take() {
+        capture_stream(service_name, LogStream::Stdout, stdout);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        capture_stream(service_name, LogStream::Stderr, stderr);
+    }
+}
+
+/// Read a pipe or FIFO line by line in the background until every writer closes it
+pub fn capture_stream<R: Read + Send + 'static>(service_name: &str, stream: LogStream, pipe: R) {
+    let service_name = service_name.to_string();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf);
+                    append(&service_name, stream, line.trim_end_matches(['\r', '\n']));
+                }
+            }
+        }
+    });
+}
+
+/// Record one line of service output
+fn append(service_name: &str, stream: LogStream, line: &str) {
+    let mut logs = SERVICE_LOGS.lock().unwrap();
+    let log = logs
+        .entry(service_name.to_string())
+        .or_insert_with(ServiceLog::new);
+
+    let entry = LogLine {
+        seq: log.next_seq,
+        timestamp: chrono::Local::now().to_rfc3339(),
+        stream,
+        line: line.to_string(),
+    };
+    log.next_seq += 1;
+
+    if log.lines.len() == LOG_BUFFER_LINES {
+        log.lines.pop_front();
+    }
+    log.lines.push_back(entry.clone());
+
+    // No followers is the common case, not an error
+    let _ = log.followers.send(entry.clone());
+    drop(logs);
+
+    log_files::write(service_name, &entry);
+}
+
+/// Most recent `count` lines for a service, oldest first
+pub fn tail(service_name: &str, count: usize) -> Vec<LogLine> {
+    let logs = SERVICE_LOGS.lock().unwrap();
+    match logs.get(service_name) {
+        Some(log) => {
+            let skip = log.lines.len().saturating_sub(count);
+            log.lines.iter().skip(skip).cloned().collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Backlog of `count` lines plus a receiver for everything logged afterwards
+///
+/// Both are taken under one lock so no line is missed or repeated.
+fn tail_and_subscribe(service_name: &str, count: usize) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+    let mut logs = SERVICE_LOGS.lock().unwrap();
+    let log = logs
+        .entry(service_name.to_string())
+        .or_insert_with(ServiceLog::new);
+
+    let skip = log.lines.len().saturating_sub(count);
+    let backlog = log.lines.iter().skip(skip).cloned().collect();
+    (backlog, log.followers.subscribe())
+}
+
+fn sse_event(line: &LogLine) -> Bytes {
+    let data = serde_json::to_string(line).unwrap_or_default();
+    Bytes::from(format!("id: {}\nevent: log\ndata: {}\n\n", line.seq, data))
+}
+
+/// Server-Sent Events stream of a service's output, starting with the last `count` lines
+pub fn follow(service_name: &str, count: usize) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (backlog, receiver) = tail_and_subscribe(service_name, count);
+    let backlog: VecDeque<LogLine> = backlog.into();
+
+    stream::unfold((backlog, receiver), |(mut backlog, mut receiver)| async move {
+        if let Some(line) = backlog.pop_front() {
+            return Some((Ok(sse_event(&line)), (backlog, receiver)));
+        }
+
+        let chunk = match tokio::time::timeout(SSE_KEEPALIVE_INTERVAL, receiver.recv()).await {
+            Ok(Ok(line)) => sse_event(&line),
+            Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                Bytes::from(format!(": skipped {} lines\n\n", skipped))
+            }
+            Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+            // Keeps proxies from closing the connection and notices disconnected clients
+            Err(_) => Bytes::from_static(b": keep-alive\n\n"),
+        };
+        Some((Ok(chunk), (backlog, receiver)))
+    })
+}
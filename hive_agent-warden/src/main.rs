@@ -1,77 +1,268 @@
+mod auth;
+mod command;
+mod discovery;
+mod events;
+mod health;
+mod history;
+mod limits;
+mod log_files;
+mod logs;
+mod metrics;
+mod ports;
+mod process;
+mod profiles;
+mod proxy;
+mod registry;
+mod reload;
+mod replicas;
+mod restart;
+mod runtime;
+mod sandbox;
+mod settings;
+mod stats;
+mod upgrade;
+
+use events::EventKind;
+use health::{HealthCheck, HealthStatus};
+use limits::ResourceLimits;
+use ports::PortError;
+use process::{ProcessExit, ServiceProcess};
+use profiles::ProfileError;
+use registry::RegistryError;
+use restart::{RestartDecision, RestartLimits, RestartPolicy, RestartTracker};
+use runtime::ServiceRuntime;
+use sandbox::Sandbox;
+use upgrade::UpgradeError;
+
 use actix_cors::Cors;
-use actix_web::{http::header, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{http::header, middleware, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
     fs::File,
-    io::{Read, Write},
+    io::Read,
     net::TcpListener,
     path::{Path, PathBuf},
-    process::{Child, Command, Stdio},
-    sync::{Arc, Mutex, RwLock},
+    process::{Child, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 const WARDEN_PORT: u16 = 6080;
-const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
-const SERVICE_START_DELAY: Duration = Duration::from_secs(2);
+const STATE_PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+const MONITOR_TICK: Duration = Duration::from_secs(1);
+const DEPENDENCY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const DEFAULT_LOG_TAIL: usize = 100;
+const DEFAULT_HISTORY_LIMIT: usize = 500;
+
+/// Set once the warden begins shutting down so no new children are spawned
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     static ref WARDEN_STATE: Arc<Mutex<WardenState>> = Arc::new(Mutex::new(WardenState::default()));
     static ref CONFIG_PATH: RwLock<PathBuf> = RwLock::new(PathBuf::new());
-    static ref RUNNING_PROCESSES: Arc<Mutex<HashMap<String, Child>>> = Arc::new(Mutex::new(HashMap::new()));
+    static ref STATE_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+    static ref RUNNING_PROCESSES: Arc<Mutex<HashMap<String, ServiceProcess>>> = Arc::new(Mutex::new(HashMap::new()));
     static ref HEALTH_CHECK_FAILURES: Arc<Mutex<HashMap<String, u32>>> = Arc::new(Mutex::new(HashMap::new()));
+    static ref RESTART_TRACKERS: Arc<Mutex<HashMap<String, RestartTracker>>> = Arc::new(Mutex::new(HashMap::new()));
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Declarative service definition from `core_microservices.json`
+///
+/// The warden only writes these back when a service is registered or removed
+/// through the API; live status lives in `ServiceRuntime`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct ServiceConfig {
     name: String,
     #[serde(default)]
     uuid: Option<String>,
+    /// Whether the service starts by default; enable/disable calls override it at runtime
     enabled: bool,
-    running: bool,
-    healthy: bool,
-    failed: bool,
+    /// Launches in a row that may fail to spawn before the service is marked failed
     boot_attempts: u32,
+    /// Time a freshly started process has to pass its first health check
     boot_timeout_millisecs: u64,
+    /// Consecutive failed health checks before a running service is restarted
     healthcheck_attempts: u32,
     healthcheck_timeout_millisecs: u64,
+    /// Grace period between SIGTERM and SIGKILL when stopping the service
+    #[serde(default = "default_stop_timeout")]
+    stop_timeout_millisecs: u64,
     port: u16,
+    /// Copies of the service to run; the first uses `port`, the others get ports from the lease range
+    #[serde(default = "default_instances", skip_serializing_if = "is_single_instance")]
+    instances: u32,
+    /// The version this definition was written for; `/services` shows what the
+    /// running process reports instead once it answers on `version_path`
     version: String,
+    /// Endpoint the running process reports its version on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version_path: Option<String>,
     #[serde(default = "default_health_path")]
     health_path: String,
+    /// How health is probed; a plain HTTP check on `health_path` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    healthcheck: Option<HealthCheck>,
+    /// Time between health checks once the service is up
+    #[serde(default = "default_healthcheck_interval")]
+    healthcheck_interval_millisecs: u64,
+    /// Services that must be healthy before this one is started
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    restart_policy: RestartPolicy,
+    /// First restart delay; doubles on each consecutive restart
+    #[serde(default = "default_restart_backoff")]
+    restart_backoff_millisecs: u64,
+    #[serde(default = "default_restart_backoff_max")]
+    restart_backoff_max_millisecs: u64,
+    /// Restarts allowed within `crash_loop_window_secs` before the service is marked failed
+    #[serde(default = "default_max_restarts")]
+    max_restarts: u32,
+    #[serde(default = "default_crash_loop_window")]
+    crash_loop_window_secs: u64,
+    /// Program to run instead of the binary named after the service; relative
+    /// paths are resolved against the config file's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    /// Extra arguments; `${VAR}` references are expanded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    /// Extra environment variables; `${VAR}` references are expanded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+    /// Directory to run in, relative to the config file's directory; defaults to the warden's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_dir: Option<String>,
+    /// Memory, CPU and file limits applied when the service is spawned
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    limits: ResourceLimits,
+    /// User, filesystem, network and syscall restrictions; the start fails if they can't be applied
+    #[serde(default, skip_serializing_if = "Sandbox::is_empty")]
+    sandbox: Sandbox,
+}
+
+impl ServiceConfig {
+    fn restart_limits(&self) -> RestartLimits {
+        RestartLimits {
+            max_restarts: self.max_restarts,
+            window: Duration::from_secs(self.crash_loop_window_secs),
+            backoff_initial: Duration::from_millis(self.restart_backoff_millisecs),
+            backoff_max: Duration::from_millis(self.restart_backoff_max_millisecs),
+        }
+    }
+}
+
+fn default_instances() -> u32 {
+    1
+}
+
+fn is_single_instance(instances: &u32) -> bool {
+    *instances <= 1
 }
 
 fn default_health_path() -> String {
     "healthcheck/basic".to_string()
 }
 
+fn default_healthcheck_interval() -> u64 {
+    10000
+}
+
+fn default_stop_timeout() -> u64 {
+    5000
+}
+
+fn default_restart_backoff() -> u64 {
+    1000
+}
+
+fn default_restart_backoff_max() -> u64 {
+    60000
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_crash_loop_window() -> u64 {
+    300
+}
+
 #[derive(Debug, Clone, Default)]
 struct WardenState {
+    /// Service definitions, exactly as configured
     services: HashMap<String, ServiceConfig>,
-    ports_in_use: Vec<u16>,
+    /// Live status for every defined service
+    runtime: HashMap<String, ServiceRuntime>,
+    /// Service names ordered so that dependencies come first
+    start_order: Vec<String>,
+    /// The profile last activated, kept across restarts in the state file
+    active_profile: Option<String>,
 }
 
-/// Check if a port is currently in use
-fn is_port_in_use(port: u16) -> bool {
-    match TcpListener::bind(format!("127.0.0.1:{}", port)) {
-        Ok(_) => false,
-        Err(_) => true,
+impl WardenState {
+    fn is_enabled(&self, name: &str) -> bool {
+        self.runtime.get(name).is_some_and(|r| r.enabled)
+    }
+
+    /// Where each enabled service runs now, by port
+    ///
+    /// Derived rather than tracked so it can't drift from how a service was
+    /// enabled or moved; API allocations are reported as leases in `ports`.
+    fn ports_in_use(&self, leases: &[ports::PortLease]) -> Vec<u16> {
+        let mut ports: Vec<u16> = self
+            .services
+            .values()
+            .filter(|s| self.is_enabled(&s.name))
+            .map(|s| replicas::unit_port(s, 0, leases).unwrap_or(s.port))
+            .collect();
+        ports.sort();
+        ports
+    }
+
+    /// A service or one of its replicas
+    fn is_unit(&self, name: &str) -> bool {
+        self.services.contains_key(name) || self.runtime.contains_key(name)
+    }
+
+    /// Enabled services the warden supervises (everything but itself)
+    fn enabled_services(&self) -> Vec<ServiceConfig> {
+        self.services
+            .values()
+            .filter(|s| self.is_enabled(&s.name) && s.name != "hive_agent-warden")
+            .cloned()
+            .collect()
     }
-}
 
-/// Find an available port in a range
-fn find_available_port(start: u16, end: u16) -> Option<u16> {
-    for port in start..=end {
-        if !is_port_in_use(port) {
-            return Some(port);
+    /// A service's definition merged with its runtime status, as reported by the API
+    fn service_view(&self, name: &str) -> Option<serde_json::Value> {
+        let mut view = serde_json::to_value(self.services.get(name)?).ok()?;
+        if let (Some(fields), Some(runtime)) = (view.as_object_mut(), self.runtime.get(name)) {
+            if let Ok(serde_json::Value::Object(status)) = serde_json::to_value(runtime) {
+                fields.extend(status);
+            }
         }
+        Some(view)
     }
-    None
+}
+
+/// Check if a port is currently in use
+fn is_port_in_use(port: u16) -> bool {
+    TcpListener::bind(format!("127.0.0.1:{}", port)).is_err()
+}
+
+/// Find an available port in a range, skipping reserved (leased or assigned) ports
+fn find_available_port(start: u16, end: u16, reserved: &HashSet<u16>) -> Option<u16> {
+    (start..=end).find(|port| !reserved.contains(port) && !is_port_in_use(*port))
 }
 
 /// Load services configuration from JSON file
@@ -83,28 +274,154 @@ fn load_services_config(path: &Path) -> Result<Vec<ServiceConfig>> {
     Ok(services)
 }
 
-/// Save services configuration to JSON file
+/// Write service definitions back to the config file, replacing it atomically
+///
+/// Only used when services are registered or removed through the API.
 fn save_services_config(path: &Path, services: &[ServiceConfig]) -> Result<()> {
-    let json = serde_json::to_string_pretty(services)?;
-    let mut file = File::create(path)?;
-    file.write_all(json.as_bytes())?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(services)?)?;
+    std::fs::rename(&tmp, path)?;
     Ok(())
 }
 
+/// Service names end up in file paths, executable lookups and unit names, so keep them plain
+///
+/// '@' and ':' are left out because they separate a service from its replica
+/// index and a unit from its upgrade.
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Invalid service name {:?}: use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
+/// Validate a whole set of service definitions: names and ports, then dependencies
+///
+/// Used for the config file on load and reload, and for the set a registration would produce.
+fn validate_services(services: &[ServiceConfig]) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut ports: HashMap<u16, &str> = HashMap::new();
+    for service in services {
+        validate_name(&service.name)?;
+        if !names.insert(service.name.as_str()) {
+            return Err(anyhow::anyhow!("Service {} is defined more than once", service.name));
+        }
+        if service.port == 0 || service.port == WARDEN_PORT {
+            return Err(anyhow::anyhow!("Port {} of {} is reserved", service.port, service.name));
+        }
+        if let Some(other) = ports.insert(service.port, &service.name) {
+            return Err(anyhow::anyhow!(
+                "Port {} is assigned to both {} and {}",
+                service.port, other, service.name
+            ));
+        }
+    }
+    validate_dependencies(services)
+}
+
+/// Validate `depends_on` references and reject dependency cycles
+fn validate_dependencies(services: &[ServiceConfig]) -> Result<()> {
+    let names: HashSet<&str> = services.iter().map(|s| s.name.as_str()).collect();
+
+    for service in services {
+        for dep in &service.depends_on {
+            if dep == &service.name {
+                return Err(anyhow::anyhow!("Service {} depends on itself", service.name));
+            }
+            if !names.contains(dep.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Service {} depends on unknown service {}",
+                    service.name, dep
+                ));
+            }
+        }
+    }
+
+    dependency_order(services).map(|_| ())
+}
+
+/// Order services so that every service comes after its dependencies
+///
+/// Services that become ready at the same time are ordered by port so the
+/// start sequence is stable across runs.
+fn dependency_order(services: &[ServiceConfig]) -> Result<Vec<String>> {
+    let mut by_port: Vec<&ServiceConfig> = services.iter().collect();
+    by_port.sort_by_key(|s| (s.port, s.name.clone()));
+
+    let mut pending: HashMap<&str, HashSet<&str>> = services
+        .iter()
+        .map(|s| (s.name.as_str(), s.depends_on.iter().map(String::as_str).collect()))
+        .collect();
+    let mut order = Vec::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let ready: Vec<&str> = by_port
+            .iter()
+            .map(|s| s.name.as_str())
+            .filter(|name| pending.get(name).is_some_and(|deps| deps.is_empty()))
+            .collect();
+
+        if ready.is_empty() {
+            let mut cycle: Vec<&str> = pending.keys().copied().collect();
+            cycle.sort();
+            return Err(anyhow::anyhow!(
+                "Dependency cycle detected among services: {}",
+                cycle.join(", ")
+            ));
+        }
+
+        for name in ready {
+            pending.remove(name);
+            for deps in pending.values_mut() {
+                deps.remove(name);
+            }
+            order.push(name.to_string());
+        }
+    }
+
+    Ok(order)
+}
+
+/// Check whether every dependency of a service is currently up (healthy or degraded)
+fn dependencies_healthy(service: &ServiceConfig, state: &WardenState) -> bool {
+    service.depends_on.iter().all(|dep| {
+        state.runtime.get(dep).is_some_and(|d| d.is_up())
+    })
+}
+
 /// Initialize warden state from configuration file
-fn initialize_from_config(config_path: &Path) -> Result<()> {
+///
+/// Enable/disable decisions saved in the state file take precedence over the
+/// `enabled` flag in the definitions.
+fn initialize_from_config(config_path: &Path, state_path: Option<&Path>) -> Result<()> {
     let services = load_services_config(config_path)?;
+    validate_services(&services)?;
+    let start_order = dependency_order(&services)?;
+    let snapshot = state_path.and_then(runtime::load_snapshot);
     let mut state = WARDEN_STATE.lock().unwrap();
     
     // Clear existing state
     state.services.clear();
-    state.ports_in_use.clear();
+    state.runtime.clear();
+    state.start_order = start_order;
+    state.active_profile = snapshot.as_ref().and_then(|s| s.active_profile.clone());
     
     // Load services into state
     for service in services {
-        if service.enabled || service.running {
-            state.ports_in_use.push(service.port);
-        }
+        let enabled = snapshot
+            .as_ref()
+            .and_then(|s| s.services.get(&service.name))
+            .map_or(service.enabled, |saved| saved.enabled);
+        state.runtime.insert(service.name.clone(), ServiceRuntime::new(enabled));
         state.services.insert(service.name.clone(), service);
     }
     
@@ -112,251 +429,645 @@ fn initialize_from_config(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Persist current state to configuration file
-fn persist_to_config() -> Result<()> {
-    let config_path = CONFIG_PATH.read().unwrap().clone();
-    let state = WARDEN_STATE.lock().unwrap();
-    
-    let mut services: Vec<ServiceConfig> = state.services.values().cloned().collect();
-    services.sort_by(|a, b| a.port.cmp(&b.port));
-    
-    save_services_config(&config_path, &services)?;
-    Ok(())
+/// Save runtime state to the state file, if one is configured
+fn persist_state() -> Result<()> {
+    let state_path = match STATE_PATH.read().unwrap().clone() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let (runtime, active_profile) = {
+        let state = WARDEN_STATE.lock().unwrap();
+        (state.runtime.clone(), state.active_profile.clone())
+    };
+    runtime::save_snapshot(&state_path, &runtime, active_profile.as_deref())
 }
 
-/// Get executable path for a service
-fn get_service_executable(service_name: &str) -> PathBuf {
-    let exe_path = env::current_exe().unwrap();
-    let exe_dir = exe_path.parent().unwrap();
-    
-    // Service executables are in the same directory as the warden in release mode
-    // Use the service name as-is (Cargo keeps hyphens in binary names)
-    let mut exe_name = service_name.to_string();
-    
-    // Add .exe extension on Windows
-    if cfg!(target_os = "windows") {
-        exe_name.push_str(".exe");
+/// Start a service process, placing it in the cgroup named `cgroup` if it has limits
+fn start_service(service: &ServiceConfig, cgroup: &str) -> Result<Child> {
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Err(anyhow::anyhow!("Warden is shutting down"));
     }
-    
-    exe_dir.join(exe_name)
-}
 
-/// Start a service process
-fn start_service(service: &ServiceConfig) -> Result<Child> {
-    let exe_path = get_service_executable(&service.name);
-    
-    if !exe_path.exists() {
-        return Err(anyhow::anyhow!("Service executable not found: {}", exe_path.display()));
-    }
-    
+    let mut command = command::build_command(service)?;
     println!("🚀 Starting service: {} on port {}", service.name, service.port);
-    
-    // Pass the port to the service as a command line argument
-    // Services should accept --port or use environment variable
-    let child = Command::new(&exe_path)
-        .arg("--port")
-        .arg(service.port.to_string())
-        .env("SERVICE_PORT", service.port.to_string())
-        .env("WARDEN_ASSIGNED_PORT", service.port.to_string())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-    
+
+    // On Unix output goes through FIFOs in the runtime directory, which keep
+    // working if the warden restarts; otherwise through plain pipes
+    #[cfg(unix)]
+    let readers = match (
+        process::fifo::path(&service.name, logs::LogStream::Stdout),
+        process::fifo::path(&service.name, logs::LogStream::Stderr),
+    ) {
+        (Some(stdout_path), Some(stderr_path)) => {
+            let (stdout, stdout_reader) = process::fifo::open_pair(&stdout_path)?;
+            let (stderr, stderr_reader) = process::fifo::open_pair(&stderr_path)?;
+            command.stdout(stdout).stderr(stderr);
+            Some((stdout_reader, stderr_reader))
+        }
+        _ => {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            None
+        }
+    };
+    #[cfg(not(unix))]
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    // Run each service in its own process group so stop signals also reach
+    // anything it spawns, and a Ctrl-C on the warden's terminal doesn't
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    limits::apply(cgroup, &service.limits, &mut command)?;
+    // Last, so the steps before it still run with the warden's privileges
+    sandbox::apply(&service.name, &service.sandbox, &mut command)?;
+
+    let mut child = command.spawn()?;
+    #[cfg(unix)]
+    if let Some((stdout, stderr)) = readers {
+        logs::capture_stream(&service.name, logs::LogStream::Stdout, stdout);
+        logs::capture_stream(&service.name, logs::LogStream::Stderr, stderr);
+    }
+    logs::capture_output(&service.name, &mut child);
+
     Ok(child)
 }
 
-/// Stop a service process
-fn stop_service(service_name: &str) -> Result<()> {
+/// Spawn a service unless a live process is already tracked for it
+///
+/// Returns `Ok(false)` when the service was already running.
+fn launch_service(service: &ServiceConfig) -> Result<bool> {
     let mut processes = RUNNING_PROCESSES.lock().unwrap();
-    
-    if let Some(mut child) = processes.remove(service_name) {
-        println!("🛑 Stopping service: {}", service_name);
-        
-        // Try graceful termination first
-        #[cfg(unix)]
-        {
-            // On Unix, kill() sends SIGKILL by default
-            // For now, we'll just use kill() directly
-            let _ = child.kill();
-            
-            // Give it a moment to complete
-            thread::sleep(Duration::from_millis(100));
-        }
-        
-        #[cfg(windows)]
-        {
-            // On Windows, kill() is the only option
-            child.kill()?;
+    if let Some(process) = processes.get_mut(&service.name) {
+        match process.try_wait() {
+            Ok(None) => return Ok(false),
+            // Exited before the monitor noticed
+            Ok(Some(exit)) => {
+                events::emit(
+                    Some(&service.name),
+                    EventKind::Exited {
+                        code: exit.code,
+                        signal: exit.signal,
+                    },
+                );
+            }
+            Err(_) => {}
         }
-        
-        #[cfg(not(any(unix, windows)))]
-        {
-            // Fallback for other platforms
-            child.kill()?;
+    }
+
+    let child = start_service(service, &service.name)?;
+    let pid = child.id();
+    processes.insert(service.name.clone(), ServiceProcess::Child(child));
+    drop(processes);
+    process::record(&service.name, pid, service.port);
+    metrics::service_started(&service.name);
+    events::emit(Some(&service.name), EventKind::Started { pid });
+
+    // Healthy is set by the first passing health check
+    update_runtime(&service.name, |svc| {
+        svc.mark_starting(Duration::from_millis(service.boot_timeout_millisecs))
+    });
+    Ok(true)
+}
+
+/// Stop a service and its replicas, the replicas first
+fn stop_service(service_name: &str) -> Result<()> {
+    for replica in replicas::running_replicas(service_name).iter().rev() {
+        if let Err(e) = stop_unit(replica) {
+            eprintln!("❌ Failed to stop {}: {}", replica, e);
         }
-        
-        child.wait()?;
+        update_runtime(replica, ServiceRuntime::mark_stopped);
+    }
+    stop_unit(service_name)
+}
+
+/// Stop one process: a service, or a single replica of one
+///
+/// Sends SIGTERM to the process group, waits up to the service's
+/// `stop_timeout_millisecs`, then sends SIGKILL to whatever is left.
+fn stop_unit(service_name: &str) -> Result<()> {
+    // Release the process table before waiting out the grace period
+    let process = RUNNING_PROCESSES.lock().unwrap().remove(service_name);
+
+    if let Some(mut process) = process {
+        println!("🛑 Stopping service: {}", service_name);
+
+        let grace = WARDEN_STATE.lock().unwrap()
+            .services
+            .get(replicas::service_of(service_name))
+            .map(|s| s.stop_timeout_millisecs)
+            .unwrap_or_else(default_stop_timeout);
+
+        process::terminate(service_name, &mut process, Duration::from_millis(grace))?;
+        forget_process(service_name);
+        metrics::service_stopped(service_name);
+        events::emit(Some(service_name), EventKind::Stopped);
     }
     
     Ok(())
 }
 
-/// Check if a service is healthy via HTTP health check
-async fn check_service_health(service: &ServiceConfig) -> bool {
-    let health_url = format!(
-        "http://127.0.0.1:{}/{}",
-        service.port, service.health_path
-    );
-    
-    let client = match reqwest::Client::builder()
-        .timeout(Duration::from_secs(5))
-        .build() {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
-    
-    match client.get(&health_url).send().await {
-        Ok(resp) => {
-            if let Ok(text) = resp.text().await {
-                text.trim() == "true"
-            } else {
-                false
+/// Drop the runtime records and cgroup of a process that is gone
+fn forget_process(service_name: &str) {
+    process::forget(service_name);
+    limits::release(service_name);
+    #[cfg(unix)]
+    process::fifo::remove(service_name);
+}
+
+/// Wait for every dependency of a service to pass its health check
+///
+/// The monitor runs the checks; each dependency gets its own
+/// `boot_timeout_millisecs` to become healthy.
+fn wait_for_dependencies(service: &ServiceConfig) -> bool {
+    for dep_name in &service.depends_on {
+        let dep = {
+            let state = WARDEN_STATE.lock().unwrap();
+            state.services.get(dep_name).filter(|_| state.is_enabled(dep_name)).cloned()
+        };
+        let dep = match dep {
+            Some(dep) => dep,
+            None => {
+                println!("⏸️  {} waits on disabled dependency {}", service.name, dep_name);
+                return false;
+            }
+        };
+
+        let deadline = Instant::now() + Duration::from_millis(dep.boot_timeout_millisecs);
+        loop {
+            let up = WARDEN_STATE.lock().unwrap()
+                .runtime
+                .get(dep_name)
+                .is_some_and(|d| d.is_up());
+            if up {
+                break;
             }
+            if Instant::now() >= deadline {
+                println!("⏳ {} still waiting on unhealthy dependency {}", service.name, dep_name);
+                return false;
+            }
+            thread::sleep(DEPENDENCY_POLL_INTERVAL);
         }
-        Err(_) => false,
     }
+    true
 }
 
-/// Start all enabled services
+/// Start all enabled services in dependency order
+///
+/// A service is only launched once its dependencies pass a health check.
+/// Services whose dependencies do not come up in time are left for the
+/// monitor loop, which starts them as soon as their dependencies are healthy.
 fn start_enabled_services() {
-    let state = WARDEN_STATE.lock().unwrap();
-    let services: Vec<ServiceConfig> = state.services.values()
-        .filter(|s| s.enabled && s.name != "hive_agent-warden") // Don't try to start ourselves
-        .cloned()
-        .collect();
-    drop(state);
-    
-    for service in services {
-        let mut state = WARDEN_STATE.lock().unwrap();
-        let mut processes = RUNNING_PROCESSES.lock().unwrap();
-        
-        // Skip if already running
-        if processes.contains_key(&service.name) {
+    let order = WARDEN_STATE.lock().unwrap().start_order.clone();
+    start_services_in_order(&order);
+}
+
+/// Take over services a previous warden left running, so they are not started twice
+///
+/// A recorded process is adopted only if its pid still belongs to the same process
+/// (same kernel start time), its service is enabled on the same port, and it passes a
+/// health check. Anything else still running from a record is stopped, so the normal
+/// start that follows doesn't collide with it.
+fn adopt_running_services() {
+    let records = process::records();
+    if records.is_empty() {
+        return;
+    }
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    for (name, record) in records {
+        let service = {
+            let state = WARDEN_STATE.lock().unwrap();
+            let service_name = replicas::service_of(&name);
+            state.services.get(service_name)
+                .filter(|s| state.is_enabled(service_name) && s.name != "hive_agent-warden")
+                .cloned()
+        };
+        // Replicas are matched against their own unit, with its leased port
+        let service = service.and_then(|s| replicas::units(&s).into_iter().find(|unit| unit.name == name));
+        let mut adopted = match process::adopt(&record) {
+            Some(adopted) => adopted,
+            None => {
+                forget_process(&name);
+                continue;
+            }
+        };
+
+        let reason = match &service {
+            None => Some("it is no longer an enabled service".to_string()),
+            Some(service) if service.port != record.port => Some(format!(
+                "it was started on port {}, now configured as {}",
+                record.port, service.port
+            )),
+            Some(service) => {
+                let attempts = service.healthcheck_attempts.max(1);
+                let status = (0..attempts)
+                    .map(|attempt| {
+                        if attempt > 0 {
+                            thread::sleep(DEPENDENCY_POLL_INTERVAL);
+                        }
+                        rt.block_on(health::probe(service))
+                    })
+                    .find(|status| status.is_up());
+                match status {
+                    Some(status) => {
+                        attach(service, adopted, status);
+                        continue;
+                    }
+                    None => Some("it does not pass its health check".to_string()),
+                }
+            }
+        };
+
+        if let Some(reason) = reason {
+            println!("🧹 Stopping leftover {} (pid {}): {}", name, record.pid, reason);
+            let grace = service.as_ref().map_or_else(default_stop_timeout, |s| s.stop_timeout_millisecs);
+            if let Err(e) = process::terminate(&name, &mut adopted, Duration::from_millis(grace)) {
+                eprintln!("❌ Failed to stop leftover {}: {}", name, e);
+            }
+            forget_process(&name);
+        }
+    }
+}
+
+/// Track an adopted process as if it had been started by this warden
+fn attach(service: &ServiceConfig, adopted: ServiceProcess, status: HealthStatus) {
+    let pid = adopted.id();
+    #[cfg(unix)]
+    for stream in [logs::LogStream::Stdout, logs::LogStream::Stderr] {
+        let reader = process::fifo::path(&service.name, stream)
+            .map(|path| process::fifo::open_reader(&path));
+        match reader {
+            Some(Ok(reader)) => logs::capture_stream(&service.name, stream, reader),
+            Some(Err(e)) => eprintln!("⚠️  Cannot reattach to {} {}: {}", service.name, stream.as_str(), e),
+            None => {}
+        }
+    }
+    RUNNING_PROCESSES.lock().unwrap().insert(service.name.clone(), adopted);
+    metrics::service_started(&service.name);
+    update_runtime(&service.name, |svc| match status {
+        HealthStatus::Degraded => svc.mark_degraded(),
+        _ => svc.mark_healthy(),
+    });
+    events::emit(Some(&service.name), EventKind::Adopted { pid });
+    println!("🔗 Reattached to running {} (pid {})", service.name, pid);
+}
+
+/// Start the given enabled services one by one, each after its dependencies are healthy
+///
+/// `names` must already be in dependency order.
+fn start_services_in_order(names: &[String]) {
+    for name in names {
+        let service = {
+            let state = WARDEN_STATE.lock().unwrap();
+            // Don't try to start ourselves
+            match state.services.get(name) {
+                Some(s) if state.is_enabled(name) && s.name != "hive_agent-warden" => s.clone(),
+                _ => continue,
+            }
+        };
+
+        if !wait_for_dependencies(&service) {
             continue;
         }
-        
-        match start_service(&service) {
-            Ok(child) => {
-                processes.insert(service.name.clone(), child);
-                if let Some(svc) = state.services.get_mut(&service.name) {
-                    svc.running = true;
-                    svc.healthy = false; // Will be set by health check
+
+        for unit in replicas::reserve(&service) {
+            match launch_service(&unit) {
+                Ok(true) => println!("✅ Started: {}", unit.name),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("❌ Failed to start {}: {}", unit.name, e);
+                    mark_failed(&unit.name, format!("failed to start: {}", e));
+                }
+            }
+        }
+    }
+}
+
+/// Stop every running service in reverse dependency order
+fn stop_all_services() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    let order = WARDEN_STATE.lock().unwrap().start_order.clone();
+
+    for name in order.iter().rev() {
+        if let Err(e) = stop_service(name) {
+            eprintln!("❌ Failed to stop {}: {}", name, e);
+        }
+        update_runtime(name, ServiceRuntime::mark_stopped);
+    }
+}
+
+/// Mark a service as failed so the monitor stops trying to run it
+fn mark_failed(service_name: &str, reason: String) {
+    update_runtime(service_name, |svc| {
+        svc.mark_stopped();
+        svc.failed = true;
+        svc.failure_reason = Some(reason);
+    });
+}
+
+/// Change a service's runtime status, publishing `health_changed` if its summarised health moved
+///
+/// Returns `None` for unknown services.
+fn update_runtime<R>(service_name: &str, update: impl FnOnce(&mut ServiceRuntime) -> R) -> Option<R> {
+    let (result, from, to) = {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        let svc = state.runtime.get_mut(service_name)?;
+        let from = svc.health();
+        let result = update(svc);
+        (result, from, svc.health())
+    };
+    if from != to {
+        events::emit(
+            Some(service_name),
+            EventKind::HealthChanged {
+                from: from.to_string(),
+                to: to.to_string(),
+            },
+        );
+    }
+    Some(result)
+}
+
+enum ProcessStatus {
+    Alive,
+    Exited(ProcessExit),
+    NotRunning,
+}
+
+/// Check a service's tracked process, reaping it if it has exited
+fn poll_process(service_name: &str) -> ProcessStatus {
+    let mut processes = RUNNING_PROCESSES.lock().unwrap();
+    let exited = match processes.get_mut(service_name) {
+        None => return ProcessStatus::NotRunning,
+        Some(process) => match process.try_wait() {
+            Ok(Some(exit)) => exit,
+            _ => return ProcessStatus::Alive,
+        },
+    };
+    processes.remove(service_name);
+    drop(processes);
+    forget_process(service_name);
+    metrics::service_stopped(service_name);
+    ProcessStatus::Exited(exited)
+}
+
+/// Schedule a restart under the service's backoff, or fail it if it is crash-looping
+fn schedule_restart(service: &ServiceConfig, cause: &str) {
+    let limits = service.restart_limits();
+    let mut trackers = RESTART_TRACKERS.lock().unwrap();
+    let tracker = trackers.entry(service.name.clone()).or_default();
+
+    match tracker.schedule(&limits) {
+        RestartDecision::After(delay) => {
+            println!(
+                "🔄 Restarting {} in {:.1}s ({})",
+                service.name,
+                delay.as_secs_f32(),
+                cause
+            );
+        }
+        RestartDecision::CrashLoop => {
+            let reason = format!(
+                "crash loop: {} restarts within {}s, last {}",
+                tracker.recent_restarts(),
+                limits.window.as_secs(),
+                cause
+            );
+            drop(trackers);
+            eprintln!("❌ {} marked failed: {}", service.name, reason);
+            mark_failed(&service.name, reason);
+        }
+    }
+}
+
+/// React to a service process that exited on its own
+fn handle_exit(service: &ServiceConfig, exit: ProcessExit) {
+    let cause = exit.describe();
+    println!("💥 {} {}", service.name, cause);
+    events::emit(
+        Some(&service.name),
+        EventKind::Exited {
+            code: exit.code,
+            signal: exit.signal,
+        },
+    );
+
+    update_runtime(&service.name, ServiceRuntime::mark_stopped);
+    HEALTH_CHECK_FAILURES.lock().unwrap().remove(&service.name);
+
+    let failure = !exit.success();
+    if service.restart_policy.restarts_after(failure) {
+        schedule_restart(service, &cause);
+        return;
+    }
+
+    RESTART_TRACKERS.lock().unwrap()
+        .entry(service.name.clone())
+        .or_default()
+        .hold();
+    if failure {
+        mark_failed(&service.name, format!("{} (restart policy: never)", cause));
+    } else {
+        println!("⏹️  {} finished; not restarting under its restart policy", service.name);
+    }
+}
+
+/// Run a health check and restart the service after repeated failures
+///
+/// A starting service is only judged against its boot deadline; after its
+/// first passing check, `healthcheck_attempts` consecutive failures restart it.
+/// A degraded answer counts as up and is not a failure.
+fn check_and_recover(rt: &tokio::runtime::Runtime, service: &ServiceConfig) {
+    let started = Instant::now();
+    let status = rt.block_on(health::probe(service));
+    metrics::record_health_check(&service.name, status.is_up(), started.elapsed());
+
+    let outcome = update_runtime(&service.name, |svc| {
+        let previous = svc.health();
+        match status {
+            HealthStatus::Healthy => svc.mark_healthy(),
+            HealthStatus::Degraded => svc.mark_degraded(),
+            HealthStatus::Unhealthy => svc.mark_unhealthy(),
+        }
+        (previous != svc.health(), svc.starting, svc.boot_timed_out())
+    });
+    let (changed, starting, boot_timed_out) = match outcome {
+        Some(outcome) => outcome,
+        None => return,
+    };
+
+    if status.is_up() {
+        if changed {
+            match status {
+                HealthStatus::Healthy => println!("💚 {} is healthy", service.name),
+                _ => println!("🟡 {} is degraded", service.name),
+            }
+        }
+        // Asked once per process, until it answers
+        let version_unknown = service.version_path.is_some()
+            && WARDEN_STATE.lock().unwrap()
+                .runtime
+                .get(&service.name)
+                .is_some_and(|svc| svc.version.is_none());
+        if version_unknown {
+            if let Some(version) = rt.block_on(health::version(service)) {
+                update_runtime(&service.name, |svc| svc.version = Some(version));
+            }
+        }
+        // Reset failure count on success
+        HEALTH_CHECK_FAILURES.lock().unwrap().remove(&service.name);
+        if let Some(tracker) = RESTART_TRACKERS.lock().unwrap().get_mut(&service.name) {
+            tracker.recovered();
+        }
+        return;
+    }
+
+    if starting {
+        if boot_timed_out {
+            let reason = format!(
+                "not healthy within boot timeout of {}ms",
+                service.boot_timeout_millisecs
+            );
+            eprintln!("❌ {} marked failed: {}", service.name, reason);
+            let _ = stop_unit(&service.name);
+            RESTART_TRACKERS.lock().unwrap()
+                .entry(service.name.clone())
+                .or_default()
+                .hold();
+            mark_failed(&service.name, reason);
+        }
+        return;
+    }
+
+    let failures = {
+        let mut failures = HEALTH_CHECK_FAILURES.lock().unwrap();
+        let count = failures.entry(service.name.clone()).or_insert(0);
+        *count += 1;
+        *count
+    };
+
+    if failures < service.healthcheck_attempts.max(1) || !service.restart_policy.restarts_after(true) {
+        return;
+    }
+
+    println!("🔄 Stopping unhealthy service: {}", service.name);
+    let _ = stop_unit(&service.name);
+    update_runtime(&service.name, ServiceRuntime::mark_stopped);
+    HEALTH_CHECK_FAILURES.lock().unwrap().remove(&service.name);
+    schedule_restart(service, &format!("{} failed health checks", failures));
+}
+
+/// Start a service that should be running once its dependencies and backoff allow
+fn start_if_due(service: &ServiceConfig) {
+    let ready = {
+        let state = WARDEN_STATE.lock().unwrap();
+        let failed = state.runtime.get(&service.name).is_none_or(|s| s.failed);
+        // The monitor's list can be a tick old; a disable since then wins
+        let enabled = state.is_enabled(replicas::service_of(&service.name));
+        enabled && !failed && dependencies_healthy(service, &state)
+    };
+    let due = RESTART_TRACKERS.lock().unwrap()
+        .get(&service.name)
+        .is_none_or(|tracker| tracker.is_due());
+    if !ready || !due {
+        return;
+    }
+
+    println!("🔄 Starting stopped service: {}", service.name);
+    match launch_service(service) {
+        Ok(launched) => {
+            // A tracker only exists once a restart has been scheduled
+            if let Some(tracker) = RESTART_TRACKERS.lock().unwrap().get_mut(&service.name) {
+                tracker.started();
+                if launched {
+                    metrics::record_restart(&service.name);
+                    events::emit(Some(&service.name), EventKind::Restarted);
                 }
-                println!("✅ Started: {}", service.name);
             }
-            Err(e) => {
-                eprintln!("❌ Failed to start {}: {}", service.name, e);
-                if let Some(svc) = state.services.get_mut(&service.name) {
-                    svc.running = false;
-                    svc.failed = true;
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to start {}: {}", service.name, e);
+            let attempts = {
+                let mut trackers = RESTART_TRACKERS.lock().unwrap();
+                let tracker = trackers.entry(service.name.clone()).or_default();
+                let attempts = tracker.launch_failed();
+                // Out of boot attempts; a missing binary won't appear by retrying
+                if attempts >= service.boot_attempts.max(1) {
+                    tracker.hold();
                 }
+                attempts
+            };
+            if attempts >= service.boot_attempts.max(1) {
+                mark_failed(&service.name, format!("failed to start {} times in a row: {}", attempts, e));
+            } else {
+                schedule_restart(service, &format!("failed to start: {}", e));
             }
         }
-        
-        // Small delay between service starts
-        thread::sleep(SERVICE_START_DELAY);
     }
 }
 
 /// Monitor services and restart if needed
+///
+/// Exited processes are noticed every tick; health checks run every tick
+/// while a service is starting, then every `healthcheck_interval_millisecs`.
+/// Each replica of a service is watched and restarted on its own.
 fn monitor_services_loop() {
     thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut last_health_check: HashMap<String, Instant> = HashMap::new();
+        let mut last_persist = Instant::now();
+        let mut last_sample = Instant::now();
         
         loop {
-            thread::sleep(HEALTH_CHECK_INTERVAL);
+            thread::sleep(MONITOR_TICK);
+            if SHUTTING_DOWN.load(Ordering::SeqCst) {
+                break;
+            }
             
-            let state = WARDEN_STATE.lock().unwrap();
-            let services: Vec<ServiceConfig> = state.services.values()
-                .filter(|s| s.enabled && s.name != "hive_agent-warden")
-                .cloned()
-                .collect();
-            drop(state);
+            // An upgrade waits for the tick to end before it moves a unit to a new process
+            let _switch_guard = upgrade::SWITCH_LOCK.lock().unwrap();
+            let services = WARDEN_STATE.lock().unwrap().enabled_services();
+            let units: Vec<ServiceConfig> = services.iter().flat_map(replicas::units).collect();
             
-            for service in services {
-                // Check if process is still alive
-                let mut processes = RUNNING_PROCESSES.lock().unwrap();
-                let is_alive = if let Some(child) = processes.get_mut(&service.name) {
-                    matches!(child.try_wait(), Ok(None))
-                } else {
-                    false
-                };
-                drop(processes);
-                
-                // Update running status
-                let mut state = WARDEN_STATE.lock().unwrap();
-                if let Some(svc) = state.services.get_mut(&service.name) {
-                    svc.running = is_alive;
-                    
-                    if is_alive {
-                        // Check health
-                        let healthy = rt.block_on(check_service_health(&service));
-                        svc.healthy = healthy;
-                        
-                        if !healthy {
-                            let mut failures = HEALTH_CHECK_FAILURES.lock().unwrap();
-                            let count = failures.entry(service.name.clone()).or_insert(0);
-                            *count += 1;
-                            
-                            // Restart after 3 consecutive failures
-                            if *count >= 3 && svc.boot_attempts > 0 {
-                                drop(state);
-                                drop(failures);
-                                
-                                println!("🔄 Restarting unhealthy service: {}", service.name);
-                                let _ = stop_service(&service.name);
-                                thread::sleep(Duration::from_secs(1));
-                                
-                                // Try to restart
-                                if let Ok(child) = start_service(&service) {
-                                    let mut processes = RUNNING_PROCESSES.lock().unwrap();
-                                    processes.insert(service.name.clone(), child);
-                                    
-                                    let mut state = WARDEN_STATE.lock().unwrap();
-                                    if let Some(svc) = state.services.get_mut(&service.name) {
-                                        svc.running = true;
-                                        svc.boot_attempts -= 1;
-                                    }
-                                    
-                                    let mut failures = HEALTH_CHECK_FAILURES.lock().unwrap();
-                                    failures.remove(&service.name);
-                                }
-                            }
-                        } else {
-                            // Reset failure count on success
-                            let mut failures = HEALTH_CHECK_FAILURES.lock().unwrap();
-                            failures.remove(&service.name);
-                        }
-                    } else if svc.enabled && !svc.failed {
-                        // Service should be running but isn't - try to start it
-                        drop(state);
-                        
-                        println!("🔄 Starting stopped service: {}", service.name);
-                        if let Ok(child) = start_service(&service) {
-                            let mut processes = RUNNING_PROCESSES.lock().unwrap();
-                            processes.insert(service.name.clone(), child);
-                            
-                            let mut state = WARDEN_STATE.lock().unwrap();
-                            if let Some(svc) = state.services.get_mut(&service.name) {
-                                svc.running = true;
-                            }
+            for service in units {
+                match poll_process(&service.name) {
+                    ProcessStatus::Alive => {
+                        let starting = WARDEN_STATE.lock().unwrap()
+                            .runtime
+                            .get(&service.name)
+                            .is_some_and(|r| r.starting);
+                        let interval = Duration::from_millis(service.healthcheck_interval_millisecs);
+                        let due = starting || last_health_check
+                            .get(&service.name)
+                            .is_none_or(|at| at.elapsed() >= interval);
+                        if due {
+                            last_health_check.insert(service.name.clone(), Instant::now());
+                            check_and_recover(&rt, &service);
                         }
                     }
+                    ProcessStatus::Exited(status) => handle_exit(&service, status),
+                    ProcessStatus::NotRunning => start_if_due(&service),
                 }
             }
             
+            if last_sample.elapsed() >= stats::SAMPLE_INTERVAL {
+                let pids: HashMap<String, u32> = RUNNING_PROCESSES.lock().unwrap()
+                    .iter()
+                    .map(|(name, process)| (name.clone(), process.id()))
+                    .collect();
+                stats::sample_all(&pids);
+                last_sample = Instant::now();
+            }
+
             // Persist state changes
-            let _ = persist_to_config();
+            if last_persist.elapsed() >= STATE_PERSIST_INTERVAL {
+                let _ = persist_state();
+                ports::purge_expired();
+                replicas::prune();
+                last_persist = Instant::now();
+            }
         }
     });
 }
@@ -374,157 +1085,762 @@ async fn healthcheck_handler() -> impl Responder {
 
 /// GET /api/v1/warden/status - Get current warden status
 async fn status_handler() -> impl Responder {
+    let leases = ports::leases();
     let state = WARDEN_STATE.lock().unwrap();
     let response = serde_json::json!({
         "status": "operational",
         "services_count": state.services.len(),
-        "ports_in_use": state.ports_in_use,
+        "ports_in_use": state.ports_in_use(&leases),
+        "leased_ports": leases.iter().map(|lease| lease.port).collect::<Vec<_>>(),
+        "active_profile": profiles::active(&state),
         "timestamp": chrono::Local::now().to_rfc3339(),
     });
     HttpResponse::Ok().json(response)
 }
 
 /// GET /api/v1/warden/services - Get all services
+///
+/// Each entry is the service definition merged with its runtime status, plus
+/// the status of each replica when it runs more than one instance, and
+/// `running_port` when an upgrade has moved it off its configured port.
 async fn services_handler() -> impl Responder {
+    let pids: HashMap<String, u32> = RUNNING_PROCESSES.lock().unwrap()
+        .iter()
+        .map(|(name, process)| (name.clone(), process.id()))
+        .collect();
+    let leases = ports::leases();
+
     let state = WARDEN_STATE.lock().unwrap();
-    let services: Vec<ServiceConfig> = state.services.values().cloned().collect();
+    let mut names: Vec<&String> = state.services.keys().collect();
+    names.sort_by_key(|name| state.services[*name].port);
+    let mut services: Vec<serde_json::Value> = names
+        .into_iter()
+        .filter_map(|name| {
+            let mut view = state.service_view(name)?;
+            let service = &state.services[name];
+            if let Some(port) = replicas::unit_port(service, 0, &leases).filter(|port| *port != service.port) {
+                view["running_port"] = port.into();
+            }
+            if service.instances > 1 {
+                view["replicas"] = serde_json::json!(replicas::views(&state, service, &leases, &pids));
+            }
+            Some(view)
+        })
+        .collect();
+    drop(state);
+
+    for view in &mut services {
+        let usage = view["name"].as_str()
+            .and_then(|name| Some((name, *pids.get(name)?)))
+            .and_then(|(name, pid)| limits::usage(name, pid));
+        if let (Some(fields), Some(usage)) = (view.as_object_mut(), usage) {
+            fields.insert("resource_usage".to_string(), serde_json::json!(usage));
+        }
+    }
     HttpResponse::Ok().json(services)
 }
 
+/// GET /api/v1/warden/resolve/{name} - Current address and health of one service
+async fn resolve_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    let host = discovery::host_from_header(req.connection_info().host());
+    match discovery::resolve(&name, &host) {
+        Some(location) => HttpResponse::Ok().json(location),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Service {} not found", name)
+        })),
+    }
+}
+
+/// GET /api/v1/warden/catalog - Addresses and health of every service
+async fn catalog_handler(req: HttpRequest) -> impl Responder {
+    let host = discovery::host_from_header(req.connection_info().host());
+    HttpResponse::Ok().json(serde_json::json!({
+        "services": discovery::catalog(&host),
+        "timestamp": chrono::Local::now().to_rfc3339(),
+    }))
+}
+
+/// Record a change requested through the API, with who asked and how it went
+fn record_action(req: &HttpRequest, action: &str, service: Option<&str>, response: &HttpResponse) {
+    let caller = req
+        .peer_addr()
+        .map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+    let token = req.extensions().get::<auth::Caller>().map(|c| c.token_name.clone());
+    events::emit(
+        service,
+        EventKind::Action {
+            action: action.to_string(),
+            caller,
+            token,
+            status: response.status().as_u16(),
+        },
+    );
+}
+
+/// Map a registry refusal onto an HTTP error response
+fn registry_error_response(error: RegistryError) -> HttpResponse {
+    let mut response = match &error {
+        RegistryError::Invalid(_) => HttpResponse::BadRequest(),
+        RegistryError::Conflict(_) => HttpResponse::Conflict(),
+        RegistryError::NotFound(_) => HttpResponse::NotFound(),
+        RegistryError::Failed(_) => HttpResponse::InternalServerError(),
+    };
+    response.json(serde_json::json!({
+        "status": "error",
+        "message": error.to_string()
+    }))
+}
+
+/// POST /api/v1/warden/services - Register a new service
+///
+/// The body is a service definition as it appears in `core_microservices.json`.
+/// It is validated, written to the config file, and started if enabled.
+async fn register_service_handler(req: HttpRequest, body: web::Json<serde_json::Value>) -> impl Responder {
+    let service: ServiceConfig = match serde_json::from_value(body.into_inner()) {
+        Ok(service) => service,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Invalid service definition: {}", e)
+            }));
+        }
+    };
+    let name = service.name.clone();
+
+    let response = match web::block(move || registry::register_service(service)).await {
+        Ok(Ok(())) => HttpResponse::Created().json(serde_json::json!({
+            "status": "success",
+            "message": format!("{} registered", name),
+            "service": WARDEN_STATE.lock().unwrap().service_view(&name)
+        })),
+        Ok(Err(e)) => registry_error_response(e),
+        Err(e) => registry_error_response(RegistryError::Failed(e.into())),
+    };
+    record_action(&req, "register", Some(&name), &response);
+    response
+}
+
+/// DELETE /api/v1/warden/service/{name} - Stop a service and remove its definition
+async fn unregister_service_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    let remove_name = name.clone();
+
+    let response = match web::block(move || registry::unregister_service(&remove_name)).await {
+        Ok(Ok(service)) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": format!("{} removed", name),
+            "service": service
+        })),
+        Ok(Err(e)) => registry_error_response(e),
+        Err(e) => registry_error_response(RegistryError::Failed(e.into())),
+    };
+    record_action(&req, "unregister", Some(&name), &response);
+    response
+}
+
 /// POST /api/v1/warden/service/{name}/enable - Enable a service
-async fn enable_service_handler(path: web::Path<String>) -> impl Responder {
+async fn enable_service_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
     let name = path.into_inner();
-    let mut state = WARDEN_STATE.lock().unwrap();
+    let enabled = WARDEN_STATE.lock().unwrap().services.get(&name).cloned();
+    if enabled.is_some() {
+        update_runtime(&name, |runtime| {
+            runtime.enabled = true;
+            runtime.failed = false; // Reset failed status when enabling
+            runtime.failure_reason = None;
+        });
+    }
 
-    if let Some(service) = state.services.get_mut(&name) {
-        service.enabled = true;
-        service.failed = false; // Reset failed status when enabling
-        let service_copy = service.clone();
+    let response = if let Some(service_copy) = enabled {
+        RESTART_TRACKERS.lock().unwrap().remove(&name);
+        HEALTH_CHECK_FAILURES.lock().unwrap().remove(&name);
+        replicas::reset(&name);
         
-        // Release lock before persisting
-        drop(state);
-        
-        // Persist changes to config file
-        if let Err(e) = persist_to_config() {
-            eprintln!("Failed to persist config: {}", e);
+        // Persist changes to the state file
+        if let Err(e) = persist_state() {
+            eprintln!("Failed to persist state: {}", e);
         }
         
-        // Start the service if it's not already running
-        let processes = RUNNING_PROCESSES.lock().unwrap();
-        if !processes.contains_key(&name) {
-            drop(processes);
-            if let Ok(child) = start_service(&service_copy) {
-                let mut processes = RUNNING_PROCESSES.lock().unwrap();
-                processes.insert(name.clone(), child);
-                
-                let mut state = WARDEN_STATE.lock().unwrap();
-                if let Some(svc) = state.services.get_mut(&name) {
-                    svc.running = true;
+        // Start the service now if its dependencies are up; otherwise the
+        // monitor loop starts it once they become healthy. Spawning happens
+        // off the worker thread, like stopping.
+        let _ = web::block(move || {
+            let deps_ready = dependencies_healthy(&service_copy, &WARDEN_STATE.lock().unwrap());
+            if deps_ready {
+                for unit in replicas::reserve(&service_copy) {
+                    let _ = launch_service(&unit);
                 }
+            } else {
+                println!(
+                    "⏳ {} enabled, waiting on dependencies: {:?}",
+                    service_copy.name, service_copy.depends_on
+                );
             }
-        }
+        })
+        .await;
         
         HttpResponse::Ok().json(serde_json::json!({
             "status": "success",
             "message": format!("{} enabled", name),
-            "service": service_copy
+            "service": WARDEN_STATE.lock().unwrap().service_view(&name)
         }))
     } else {
         HttpResponse::NotFound().json(serde_json::json!({
             "status": "error",
             "message": format!("Service {} not found", name)
         }))
-    }
+    };
+    record_action(&req, "enable", Some(&name), &response);
+    response
 }
 
 /// POST /api/v1/warden/service/{name}/disable - Disable a service
-async fn disable_service_handler(path: web::Path<String>) -> impl Responder {
+async fn disable_service_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
     let name = path.into_inner();
-    let mut state = WARDEN_STATE.lock().unwrap();
+    let disabled = update_runtime(&name, |runtime| {
+        runtime.enabled = false;
+        runtime.mark_stopped();
+    });
 
-    if let Some(service) = state.services.get_mut(&name) {
-        service.enabled = false;
-        service.running = false;
-        service.healthy = false;
-        let service_copy = service.clone();
-        
-        // Release lock before persisting
-        drop(state);
+    let response = if disabled.is_some() {
+        // Stop the service process off the worker thread; this can take up
+        // to the service's stop timeout
+        let stop_name = name.clone();
+        let _ = web::block(move || stop_service(&stop_name)).await;
         
-        // Stop the service process
-        let _ = stop_service(&name);
-        
-        // Persist changes to config file
-        if let Err(e) = persist_to_config() {
-            eprintln!("Failed to persist config: {}", e);
+        // Persist changes to the state file
+        if let Err(e) = persist_state() {
+            eprintln!("Failed to persist state: {}", e);
         }
         
         HttpResponse::Ok().json(serde_json::json!({
             "status": "success",
             "message": format!("{} disabled", name),
-            "service": service_copy
+            "service": WARDEN_STATE.lock().unwrap().service_view(&name)
         }))
     } else {
         HttpResponse::NotFound().json(serde_json::json!({
             "status": "error",
             "message": format!("Service {} not found", name)
         }))
-    }
+    };
+    record_action(&req, "disable", Some(&name), &response);
+    response
+}
+
+/// POST /api/v1/warden/service/{name}/upgrade - Replace a service's processes without downtime
+async fn upgrade_service_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    let upgrade_name = name.clone();
+
+    // Waits for the new processes to pass their health checks
+    let response = match web::block(move || upgrade::upgrade(&upgrade_name)).await {
+        Ok(Ok(units)) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": format!("{} upgraded", name),
+            "units": units,
+            "service": WARDEN_STATE.lock().unwrap().service_view(&name)
+        })),
+        Ok(Err(e)) => {
+            let mut response = match e {
+                UpgradeError::NotFound(_) => HttpResponse::NotFound(),
+                UpgradeError::Conflict(_) => HttpResponse::Conflict(),
+                UpgradeError::RolledBack(_) => HttpResponse::InternalServerError(),
+            };
+            response.json(serde_json::json!({
+                "status": "error",
+                "message": e.to_string()
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Upgrade failed: {}", e)
+        })),
+    };
+    record_action(&req, "upgrade", Some(&name), &response);
+    response
+}
+
+/// GET /api/v1/warden/profiles - Configured profiles and which one is active
+async fn profiles_handler() -> impl Responder {
+    let state = WARDEN_STATE.lock().unwrap();
+    HttpResponse::Ok().json(serde_json::json!({
+        "active_profile": profiles::active(&state),
+        "profiles": profiles::list(&state),
+    }))
+}
+
+/// POST /api/v1/warden/profile/{name}/activate - Enable exactly the services of a profile
+async fn activate_profile_handler(req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    let activate_name = name.clone();
+
+    // Stopping the services that leave can take up to their stop timeouts
+    let response = match web::block(move || profiles::activate(&activate_name)).await {
+        Ok(Ok(activation)) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": format!("Profile {} activated", name),
+            "profile": activation.profile,
+            "enabled": activation.enabled,
+            "disabled": activation.disabled
+        })),
+        Ok(Err(e)) => {
+            let mut response = match e {
+                ProfileError::NotFound(_) => HttpResponse::NotFound(),
+                ProfileError::Invalid(_) => HttpResponse::Conflict(),
+            };
+            response.json(serde_json::json!({
+                "status": "error",
+                "message": e.to_string()
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Profile activation failed: {}", e)
+        })),
+    };
+    record_action(&req, "activate_profile", None, &response);
+    response
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsQuery {
+    tail: Option<usize>,
+    #[serde(default)]
+    follow: bool,
 }
 
-/// POST /api/v1/warden/port/allocate - Allocate a new port for a service
-async fn allocate_port_handler(
-    body: web::Json<serde_json::Value>,
+/// GET /api/v1/warden/service/{name}/logs - Recent service output
+///
+/// `?tail=N` limits the number of lines (default 100); `?follow=true` streams
+/// new lines as Server-Sent Events after the tail.
+async fn service_logs_handler(
+    path: web::Path<String>,
+    query: web::Query<LogsQuery>,
 ) -> impl Responder {
-    let service_name = body.get("service_name").and_then(|v| v.as_str());
-    let preferred_port = body.get("preferred_port").and_then(|v| v.as_u64()).map(|p| p as u16);
-
-    match (service_name, preferred_port) {
-        (Some(name), Some(port)) => {
-            if !is_port_in_use(port) {
-                let mut state = WARDEN_STATE.lock().unwrap();
-                state.ports_in_use.push(port);
+    let name = path.into_inner();
+    if !WARDEN_STATE.lock().unwrap().is_unit(&name) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Service {} not found", name)
+        }));
+    }
+
+    let count = query.tail.unwrap_or(DEFAULT_LOG_TAIL);
+    if query.follow {
+        return HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(logs::follow(&name, count));
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "service": name,
+        "lines": logs::tail(&name, count)
+    }))
+}
+
+/// GET /api/v1/warden/service/{name}/stats - Recent CPU, memory, thread and descriptor samples
+async fn service_stats_handler(path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    if !WARDEN_STATE.lock().unwrap().is_unit(&name) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Service {} not found", name)
+        }));
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "service": name,
+        "interval_secs": stats::SAMPLE_INTERVAL.as_secs(),
+        "samples": stats::history(&name)
+    }))
+}
+
+/// GET /api/v1/warden/service/{name}/logs/archives - List persisted log segments
+async fn log_archives_handler(path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    if !WARDEN_STATE.lock().unwrap().is_unit(&name) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Service {} not found", name)
+        }));
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "service": name,
+        "segments": log_files::list_segments(&name)
+    }))
+}
+
+/// GET /api/v1/warden/service/{name}/logs/archives/{file} - Download a log segment
+async fn log_archive_file_handler(path: web::Path<(String, String)>) -> impl Responder {
+    let (name, file) = path.into_inner();
+    if !WARDEN_STATE.lock().unwrap().is_unit(&name) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Service {} not found", name)
+        }));
+    }
+    let segment = match log_files::segment_path(&name, &file) {
+        Some(segment) => segment,
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Log segment {} not found for {}", file, name)
+            }));
+        }
+    };
+
+    match web::block(move || std::fs::read(segment)).await {
+        Ok(Ok(data)) => {
+            let content_type = if file.ends_with(".gz") { "application/gzip" } else { "text/plain" };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file)))
+                .body(data)
+        }
+        _ => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to read log segment {}", file)
+        })),
+    }
+}
+
+/// POST /api/v1/warden/reload - Re-read the config file and apply changes
+async fn reload_handler(req: HttpRequest) -> impl Responder {
+    let response = match web::block(reload::reload_config).await {
+        Ok(Ok(diff)) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": if diff.is_empty() { "No changes" } else { "Configuration reloaded" },
+            "changes": diff
+        })),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Invalid configuration, nothing applied: {}", e)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Reload failed: {}", e)
+        })),
+    };
+    record_action(&req, "reload", None, &response);
+    response
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    since: Option<u64>,
+    #[serde(flatten)]
+    filter: events::EventFilter,
+}
+
+/// The replay cursor: `?since=N`, or the `Last-Event-ID` an SSE client sends on reconnect
+fn events_cursor(req: &HttpRequest, query: &EventsQuery) -> Option<u64> {
+    query.since.or_else(|| {
+        req.headers()
+            .get("Last-Event-ID")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    })
+}
+
+/// GET /api/v1/warden/events - Lifecycle events as Server-Sent Events
+///
+/// `?since=N` first replays buffered events after sequence number N;
+/// `?service=` and `?type=` (comma-separated) narrow the stream.
+async fn events_handler(req: HttpRequest, query: web::Query<EventsQuery>) -> impl Responder {
+    let query = query.into_inner();
+    let since = events_cursor(&req, &query);
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events::follow(since, query.filter))
+}
+
+/// GET /api/v1/warden/events/ws - Lifecycle events over a WebSocket, one JSON message each
+async fn events_websocket_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<EventsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    let since = events_cursor(&req, &query);
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(events::forward_to_websocket(session, messages, since, query.filter));
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryParams {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    #[serde(flatten)]
+    filter: events::EventFilter,
+}
+
+/// GET /api/v1/warden/history - Recorded events and API actions
+///
+/// `?service=`, `?type=` (comma-separated), `?from=` and `?to=` (RFC 3339)
+/// narrow the results; the most recent `?limit=N` matches (default 500) are
+/// returned oldest first.
+async fn history_handler(query: web::Query<HistoryParams>) -> impl Responder {
+    let params = query.into_inner();
+    let parse_time = |value: Option<String>, field: &str| match value {
+        None => Ok(None),
+        Some(value) => chrono::DateTime::parse_from_rfc3339(&value)
+            .map(Some)
+            .map_err(|e| format!("Invalid {} {:?}, expected RFC 3339: {}", field, value, e)),
+    };
+    let range = parse_time(params.from, "from").and_then(|from| Ok((from, parse_time(params.to, "to")?)));
+    let (from, to) = match range {
+        Ok(range) => range,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }));
+        }
+    };
+
+    let query = history::HistoryQuery {
+        filter: params.filter,
+        from,
+        to,
+        limit: params.limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+    };
+    match web::block(move || history::query(&query)).await {
+        Ok(Ok(events)) => HttpResponse::Ok().json(serde_json::json!({
+            "count": events.len(),
+            "events": events
+        })),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to read history: {}", e)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to read history: {}", e)
+        })),
+    }
+}
+
+/// GET /api/v1/warden/metrics - Service and warden metrics in Prometheus text format
+async fn metrics_handler() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+/// Map a refused lease operation onto an HTTP error response
+fn port_error_response(error: PortError) -> HttpResponse {
+    let mut response = match &error {
+        PortError::Invalid(_) => HttpResponse::BadRequest(),
+        PortError::NotFound(_) => HttpResponse::NotFound(),
+        PortError::NotOwner(_) => HttpResponse::Forbidden(),
+        PortError::Exhausted(_) => HttpResponse::InternalServerError(),
+    };
+    response.json(serde_json::json!({
+        "status": "error",
+        "message": error.to_string()
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PortAllocateRequest {
+    service_name: String,
+    preferred_port: Option<u16>,
+    ttl_secs: Option<u64>,
+}
+
+/// POST /api/v1/warden/port/allocate - Lease a port to a service
+///
+/// The preferred port is leased if it is free; otherwise the first free port in
+/// the configured range is. Asking for a port the service already holds renews it.
+async fn allocate_port_handler(req: HttpRequest, body: web::Json<serde_json::Value>) -> impl Responder {
+    let request: PortAllocateRequest = match serde_json::from_value(body.into_inner()) {
+        Ok(request) => request,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Missing or invalid fields (service_name, preferred_port, ttl_secs): {}", e)
+            }));
+        }
+    };
+
+    if let Err(e) = ports::validate_owner(&request.service_name) {
+        return port_error_response(e);
+    }
+
+    let owner = request.service_name.clone();
+    let allocation = web::block(move || {
+        ports::allocate(&request.service_name, request.preferred_port, request.ttl_secs)
+    })
+    .await;
+
+    let response = match allocation {
+        Ok(Ok(allocation)) => {
+            metrics::record_port_allocation(allocation.reassigned);
+            events::emit(
+                Some(&owner),
+                EventKind::PortAllocated {
+                    port: allocation.lease.port,
+                    requested_port: request.preferred_port,
+                    reassigned: allocation.reassigned,
+                },
+            );
+            if allocation.reassigned {
                 HttpResponse::Ok().json(serde_json::json!({
-                    "status": "success",
-                    "service": name,
-                    "port": port
+                    "status": "reassigned",
+                    "service": owner,
+                    "requested_port": request.preferred_port,
+                    "assigned_port": allocation.lease.port,
+                    "lease": allocation.lease
                 }))
             } else {
-                // Find alternative port
-                if let Some(new_port) = find_available_port(6000, 7000) {
-                    let mut state = WARDEN_STATE.lock().unwrap();
-                    state.ports_in_use.push(new_port);
-                    HttpResponse::Ok().json(serde_json::json!({
-                        "status": "reassigned",
-                        "service": name,
-                        "requested_port": port,
-                        "assigned_port": new_port
-                    }))
-                } else {
-                    HttpResponse::InternalServerError().json(serde_json::json!({
-                        "status": "error",
-                        "message": "No available ports found"
-                    }))
-                }
+                HttpResponse::Ok().json(serde_json::json!({
+                    "status": "success",
+                    "service": owner,
+                    "port": allocation.lease.port,
+                    "lease": allocation.lease
+                }))
             }
         }
-        _ => HttpResponse::BadRequest().json(serde_json::json!({
-            "status": "error",
-            "message": "Missing required fields: service_name, preferred_port"
+        Ok(Err(e)) => port_error_response(e),
+        Err(e) => port_error_response(PortError::Exhausted(format!("Port allocation failed: {}", e))),
+    };
+    record_action(&req, "allocate_port", Some(&owner), &response);
+    response
+}
+
+#[derive(Debug, Deserialize)]
+struct PortLeaseRequest {
+    service_name: String,
+    port: u16,
+    ttl_secs: Option<u64>,
+}
+
+fn invalid_lease_request(error: serde_json::Error) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "status": "error",
+        "message": format!("Missing or invalid fields (service_name, port): {}", error)
+    }))
+}
+
+/// POST /api/v1/warden/port/renew - Extend a lease held by the service
+async fn renew_port_handler(req: HttpRequest, body: web::Json<serde_json::Value>) -> impl Responder {
+    let request: PortLeaseRequest = match serde_json::from_value(body.into_inner()) {
+        Ok(request) => request,
+        Err(e) => return invalid_lease_request(e),
+    };
+    if let Err(e) = ports::validate_owner(&request.service_name) {
+        return port_error_response(e);
+    }
+
+    let response = match ports::renew(&request.service_name, request.port, request.ttl_secs) {
+        Ok(lease) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "lease": lease
         })),
+        Err(e) => port_error_response(e),
+    };
+    record_action(&req, "renew_port", Some(&request.service_name), &response);
+    response
+}
+
+/// POST /api/v1/warden/port/release - Give a leased port back
+async fn release_port_handler(req: HttpRequest, body: web::Json<serde_json::Value>) -> impl Responder {
+    let request: PortLeaseRequest = match serde_json::from_value(body.into_inner()) {
+        Ok(request) => request,
+        Err(e) => return invalid_lease_request(e),
+    };
+    if let Err(e) = ports::validate_owner(&request.service_name) {
+        return port_error_response(e);
     }
+
+    let response = match ports::release(&request.service_name, request.port) {
+        Ok(lease) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": format!("Port {} released", lease.port),
+            "lease": lease
+        })),
+        Err(e) => port_error_response(e),
+    };
+    record_action(&req, "release_port", Some(&request.service_name), &response);
+    response
+}
+
+/// GET /api/v1/warden/port/leases - List live port leases
+async fn port_leases_handler() -> impl Responder {
+    let (range_start, range_end) = ports::range();
+    HttpResponse::Ok().json(serde_json::json!({
+        "range": [range_start, range_end],
+        "leases": ports::leases()
+    }))
 }
 
 /// GET /api/v1/warden/port/check/{port} - Check if a port is in use
 async fn port_check_handler(path: web::Path<u16>) -> impl Responder {
     let port = path.into_inner();
     let in_use = is_port_in_use(port);
+    let lease = ports::lease(port);
     HttpResponse::Ok().json(serde_json::json!({
         "port": port,
-        "in_use": in_use
+        "in_use": in_use,
+        "leased": lease.is_some(),
+        "owner": lease.map(|lease| lease.owner)
     }))
 }
 
+/// Malformed bodies, queries and paths answer with the same envelope as handler errors
+fn invalid_request(error: impl std::fmt::Display + std::fmt::Debug + 'static) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(serde_json::json!({
+        "status": "error",
+        "message": error.to_string()
+    }));
+    actix_web::error::InternalError::from_response(error, response).into()
+}
+
+/// Any route that doesn't exist
+async fn not_found_handler(req: HttpRequest) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "status": "error",
+        "message": format!("No route for {} {}", req.method(), req.path())
+    }))
+}
+
+/// ANY /{service}/{path} - Forward to a replica of the service that is up
+async fn proxy_handler(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let service = req.match_info().get("service").unwrap_or_default().to_string();
+    let path = req.match_info().get("path").unwrap_or_default().to_string();
+    match proxy::forward(&service, &path, &req, body).await {
+        Some(response) => response,
+        None => not_found_handler(req).await,
+    }
+}
+
+/// Resolve once the warden is asked to shut down
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigint = signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
+        let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = sigint.recv() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 #[actix_web::main]
 async fn main() -> Result<()> {
     println!("🚀 Starting Hive Agent Warden on port {}", WARDEN_PORT);
@@ -554,9 +1870,41 @@ async fn main() -> Result<()> {
         *path = config_path.clone();
     }
     
+    // Load warden-level settings that live next to the service definitions
+    let settings_path = config_path.with_file_name(settings::SETTINGS_FILE_NAME);
+    let warden_settings = settings::load_settings(&settings_path);
+
+    println!("📝 Writing service logs to {}", warden_settings.logs.directory.display());
+    if let Err(e) = log_files::configure(warden_settings.logs.clone()) {
+        eprintln!("⚠️  Failed to set up log directory: {}", e);
+    }
+
+    ports::configure(warden_settings.ports.clone());
+    process::configure(warden_settings.runtime_dir.clone());
+    limits::configure(&warden_settings.limits);
+    profiles::configure(warden_settings.profiles.clone());
+
+    if let Some(history_file) = &warden_settings.history.file {
+        println!("📜 Recording event history to {}", history_file.display());
+    }
+    history::configure(warden_settings.history.clone());
+    auth::configure(
+        warden_settings.api.tokens_file.as_deref(),
+        warden_settings.api.protect_reads,
+    );
+
+    // Runtime state goes to its own file so the definitions stay untouched
+    if let Some(state_path) = &warden_settings.state_file {
+        println!("💾 Saving runtime state to {}", state_path.display());
+    }
+    *STATE_PATH.write().unwrap() = warden_settings.state_file.clone();
+
     // Initialize state from config file
-    match initialize_from_config(&config_path) {
-        Ok(_) => println!("✅ Configuration loaded successfully"),
+    match initialize_from_config(&config_path, warden_settings.state_file.as_deref()) {
+        Ok(_) => {
+            println!("✅ Configuration loaded successfully");
+            profiles::check(&WARDEN_STATE.lock().unwrap());
+        }
         Err(e) => {
             eprintln!("⚠️  Failed to load config: {}", e);
             eprintln!("   Creating empty state...");
@@ -564,19 +1912,24 @@ async fn main() -> Result<()> {
     }
 
     println!("📋 Warden initialized");
+
+    // Reattach to services a previous warden left running before anything is started.
+    // Health checks block, so this runs off the async runtime.
+    thread::spawn(adopt_running_services).join().ok();
     
-    // Start monitoring loop
-    println!("🔍 Starting service monitoring...");
-    monitor_services_loop();
-    
-    // Give monitoring thread time to start
-    thread::sleep(Duration::from_secs(1));
-    
-    // Start all enabled services
-    println!("🚀 Starting enabled services...");
-    start_enabled_services();
+    // Bind before anything is spawned, so a taken port doesn't leave children behind.
+    // An API without tokens is never offered beyond this machine.
+    let bind_address = match (warden_settings.api.localhost_only, auth::is_enforced()) {
+        (false, true) => "0.0.0.0",
+        (false, false) => {
+            eprintln!("⚠️  localhost_only is off but no API tokens are configured; listening on 127.0.0.1 only");
+            "127.0.0.1"
+        }
+        (true, _) => "127.0.0.1",
+    };
+    println!("🌐 Listening on {}:{}", bind_address, WARDEN_PORT);
 
-    HttpServer::new(|| {
+    let server = HttpServer::new(|| {
         let cors = Cors::default()
             .allow_any_origin()
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
@@ -587,18 +1940,138 @@ async fn main() -> Result<()> {
             ]);
 
         App::new()
+            .wrap(middleware::from_fn(auth::require_token))
             .wrap(cors)
+            .app_data(web::JsonConfig::default().error_handler(|e, _| invalid_request(e)))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| invalid_request(e)))
+            .app_data(web::PathConfig::default().error_handler(|e, _| invalid_request(e)))
+            .default_service(web::to(not_found_handler))
             .route("/api/v1/warden/healthcheck/basic", web::get().to(healthcheck_handler))
             .route("/api/v1/warden/status", web::get().to(status_handler))
             .route("/api/v1/warden/services", web::get().to(services_handler))
+            .route("/api/v1/warden/services", web::post().to(register_service_handler))
+            .route("/api/v1/warden/resolve/{name}", web::get().to(resolve_handler))
+            .route("/api/v1/warden/catalog", web::get().to(catalog_handler))
+            .route("/api/v1/warden/service/{name}", web::delete().to(unregister_service_handler))
             .route("/api/v1/warden/service/{name}/enable", web::post().to(enable_service_handler))
             .route("/api/v1/warden/service/{name}/disable", web::post().to(disable_service_handler))
+            .route("/api/v1/warden/service/{name}/upgrade", web::post().to(upgrade_service_handler))
+            .route("/api/v1/warden/service/{name}/logs", web::get().to(service_logs_handler))
+            .route("/api/v1/warden/service/{name}/stats", web::get().to(service_stats_handler))
+            .route("/api/v1/warden/service/{name}/logs/archives", web::get().to(log_archives_handler))
+            .route("/api/v1/warden/service/{name}/logs/archives/{file}", web::get().to(log_archive_file_handler))
+            .route("/api/v1/warden/profiles", web::get().to(profiles_handler))
+            .route("/api/v1/warden/profile/{name}/activate", web::post().to(activate_profile_handler))
+            .route("/api/v1/warden/reload", web::post().to(reload_handler))
+            .route("/api/v1/warden/metrics", web::get().to(metrics_handler))
+            .route("/api/v1/warden/events", web::get().to(events_handler))
+            .route("/api/v1/warden/events/ws", web::get().to(events_websocket_handler))
+            .route("/api/v1/warden/history", web::get().to(history_handler))
             .route("/api/v1/warden/port/allocate", web::post().to(allocate_port_handler))
+            .route("/api/v1/warden/port/renew", web::post().to(renew_port_handler))
+            .route("/api/v1/warden/port/release", web::post().to(release_port_handler))
+            .route("/api/v1/warden/port/leases", web::get().to(port_leases_handler))
             .route("/api/v1/warden/port/check/{port}", web::get().to(port_check_handler))
+            // Anything else is for a service; registered last so the API routes win
+            .service(
+                web::resource(["/{service}", "/{service}/{path:.*}"])
+                    .app_data(web::PayloadConfig::new(proxy::MAX_BODY_BYTES))
+                    .to(proxy_handler),
+            )
     })
-    .bind(("0.0.0.0", WARDEN_PORT))?
-    .run()
-    .await?;
+    .bind((bind_address, WARDEN_PORT))?;
+
+    // Start monitoring loop
+    println!("🔍 Starting service monitoring...");
+    monitor_services_loop();
+    
+    // Give monitoring thread time to start
+    thread::sleep(Duration::from_secs(1));
+    
+    // Start all enabled services in dependency order. This waits on health
+    // checks, so it runs in the background while the API comes up.
+    println!("🚀 Starting enabled services...");
+    thread::spawn(start_enabled_services);
+
+    if warden_settings.watch_config {
+        reload::watch_config_file();
+    }
+
+    let server = server.disable_signals().run();
+
+    // Handle SIGINT/SIGTERM ourselves so children are always stopped
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        wait_for_shutdown_signal().await;
+        println!("🛑 Received shutdown signal");
+        handle.stop(true).await;
+    });
+
+    server.await?;
+
+    println!("🛑 Shutting down services...");
+    stop_all_services();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{dependency_order, validate_dependencies, ServiceConfig};
+
+    /// A minimal definition; the rest comes from the serde defaults
+    pub fn service(name: &str, port: u16, depends_on: &[&str]) -> ServiceConfig {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "enabled": true,
+            "boot_attempts": 3,
+            "boot_timeout_millisecs": 5000,
+            "healthcheck_attempts": 3,
+            "healthcheck_timeout_millisecs": 1000,
+            "port": port,
+            "version": "1.0.0",
+            "depends_on": depends_on,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn dependencies_start_first() {
+        let services = [
+            service("director", 6084, &["camera"]),
+            service("camera", 6082, &[]),
+            service("rag", 6071, &[]),
+        ];
+        assert_eq!(dependency_order(&services).unwrap(), ["rag", "camera", "director"]);
+    }
+
+    #[test]
+    fn independent_services_are_ordered_by_port() {
+        let services = [service("b", 6002, &[]), service("a", 6003, &[]), service("c", 6001, &[])];
+        assert_eq!(dependency_order(&services).unwrap(), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let services = [
+            service("a", 6001, &["c"]),
+            service("b", 6002, &["a"]),
+            service("c", 6003, &["b"]),
+            service("d", 6004, &[]),
+        ];
+        let error = dependency_order(&services).unwrap_err().to_string();
+        assert!(error.contains("a, b, c"), "{}", error);
+        assert!(validate_dependencies(&services).is_err());
+    }
+
+    #[test]
+    fn missing_and_self_dependencies_are_rejected() {
+        let missing = [service("a", 6001, &["nowhere"])];
+        let error = validate_dependencies(&missing).unwrap_err().to_string();
+        assert!(error.contains("unknown service nowhere"), "{}", error);
+
+        let itself = [service("a", 6001, &["a"])];
+        let error = validate_dependencies(&itself).unwrap_err().to_string();
+        assert!(error.contains("depends on itself"), "{}", error);
+    }
+}
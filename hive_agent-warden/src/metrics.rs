@@ -0,0 +1,314 @@
+// Metrics - counters the warden keeps for the Prometheus endpoint
+use crate::{ports, stats, HEALTH_CHECK_FAILURES, WARDEN_STATE};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::new());
+}
+
+struct Metrics {
+    warden_started: Instant,
+    services: HashMap<String, ServiceMetrics>,
+    port_allocations: u64,
+    port_reassignments: u64,
+    config_reloads: u64,
+    config_reload_failures: u64,
+}
+
+#[derive(Default)]
+struct ServiceMetrics {
+    restarts: u64,
+    health_checks: u64,
+    health_check_failures: u64,
+    last_health_latency: Option<Duration>,
+    /// When the current process was spawned
+    started: Option<Instant>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            warden_started: Instant::now(),
+            services: HashMap::new(),
+            port_allocations: 0,
+            port_reassignments: 0,
+            config_reloads: 0,
+            config_reload_failures: 0,
+        }
+    }
+
+    fn service(&mut self, name: &str) -> &mut ServiceMetrics {
+        self.services.entry(name.to_string()).or_default()
+    }
+}
+
+/// A process was spawned for the service
+pub fn service_started(name: &str) {
+    METRICS.lock().unwrap().service(name).started = Some(Instant::now());
+}
+
+/// The service's process exited or was stopped
+pub fn service_stopped(name: &str) {
+    METRICS.lock().unwrap().service(name).started = None;
+}
+
+/// The monitor brought a service back after an exit or failed health checks
+pub fn record_restart(name: &str) {
+    METRICS.lock().unwrap().service(name).restarts += 1;
+}
+
+pub fn record_health_check(name: &str, healthy: bool, latency: Duration) {
+    let mut metrics = METRICS.lock().unwrap();
+    let service = metrics.service(name);
+    service.health_checks += 1;
+    if !healthy {
+        service.health_check_failures += 1;
+    }
+    service.last_health_latency = Some(latency);
+}
+
+/// A port was handed out; `reassigned` when the preferred port was taken
+pub fn record_port_allocation(reassigned: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.port_allocations += 1;
+    if reassigned {
+        metrics.port_reassignments += 1;
+    }
+}
+
+pub fn record_reload(applied: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    if applied {
+        metrics.config_reloads += 1;
+    } else {
+        metrics.config_reload_failures += 1;
+    }
+}
+
+/// Escape a label value for the text exposition format
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write one metric family with a sample per service
+fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (service, value) in samples {
+        let _ = writeln!(out, "{}{{service=\"{}\"}} {}", name, label(service), value);
+    }
+}
+
+/// Write a single unlabelled sample
+fn scalar(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Render every metric in the Prometheus text exposition format
+pub fn render() -> String {
+    let state = WARDEN_STATE.lock().unwrap().clone();
+    let failures = HEALTH_CHECK_FAILURES.lock().unwrap().clone();
+    let leases = ports::leases();
+    let samples = stats::latest();
+    let metrics = METRICS.lock().unwrap();
+
+    let mut names: Vec<&String> = state.services.keys().collect();
+    names.sort_by_key(|name| state.services[*name].port);
+
+    let flag = |b: bool| if b { 1.0 } else { 0.0 };
+    let per_service = |f: &dyn Fn(&str) -> Option<f64>| -> Vec<(String, f64)> {
+        names
+            .iter()
+            .filter_map(|name| f(name).map(|value| ((*name).clone(), value)))
+            .collect()
+    };
+    let runtime = |name: &str| state.runtime.get(name).cloned().unwrap_or_default();
+    let service = |name: &str| metrics.services.get(name);
+    // Only the running process's usage; a stopped service has none
+    let sample = |name: &str| samples.get(name).filter(|_| runtime(name).running);
+
+    let mut out = String::new();
+    family(
+        &mut out,
+        "hive_warden_service_enabled",
+        "gauge",
+        "Whether the service is enabled",
+        &per_service(&|name| Some(flag(runtime(name).enabled))),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_up",
+        "gauge",
+        "Whether the service process is running",
+        &per_service(&|name| Some(flag(runtime(name).running))),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_healthy",
+        "gauge",
+        "Whether the service passed its last health check",
+        &per_service(&|name| Some(flag(runtime(name).healthy))),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_degraded",
+        "gauge",
+        "Whether the service reported degraded on its last health check",
+        &per_service(&|name| Some(flag(runtime(name).degraded))),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_failed",
+        "gauge",
+        "Whether the service was marked failed",
+        &per_service(&|name| Some(flag(runtime(name).failed))),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_restarts_total",
+        "counter",
+        "Restarts performed by the warden",
+        &per_service(&|name| Some(service(name).map_or(0, |s| s.restarts) as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_health_checks_total",
+        "counter",
+        "Health checks run against the service",
+        &per_service(&|name| Some(service(name).map_or(0, |s| s.health_checks) as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_health_check_failures_total",
+        "counter",
+        "Health checks the service failed; degraded answers are not failures",
+        &per_service(&|name| Some(service(name).map_or(0, |s| s.health_check_failures) as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_health_check_consecutive_failures",
+        "gauge",
+        "Health checks failed in a row",
+        &per_service(&|name| Some(failures.get(name).copied().unwrap_or(0) as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_health_check_latency_seconds",
+        "gauge",
+        "Duration of the last health check",
+        &per_service(&|name| {
+            service(name)
+                .and_then(|s| s.last_health_latency)
+                .map(|latency| latency.as_secs_f64())
+        }),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_uptime_seconds",
+        "gauge",
+        "Time since the current process was started",
+        &per_service(&|name| match service(name).and_then(|s| s.started) {
+            Some(started) if runtime(name).running => Some(started.elapsed().as_secs_f64()),
+            _ => Some(0.0),
+        }),
+    );
+
+    family(
+        &mut out,
+        "hive_warden_service_cpu_percent",
+        "gauge",
+        "CPU used by the service's process group over the last sample, as a share of one core",
+        &per_service(&|name| sample(name).map(|s| s.cpu_percent)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_memory_rss_bytes",
+        "gauge",
+        "Resident memory of the service's process group",
+        &per_service(&|name| sample(name).map(|s| s.rss_bytes as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_threads",
+        "gauge",
+        "Threads in the service's process group",
+        &per_service(&|name| sample(name).map(|s| s.threads as f64)),
+    );
+    family(
+        &mut out,
+        "hive_warden_service_open_fds",
+        "gauge",
+        "Open file descriptors in the service's process group",
+        &per_service(&|name| sample(name).map(|s| s.open_fds as f64)),
+    );
+
+    scalar(
+        &mut out,
+        "hive_warden_services",
+        "gauge",
+        "Services defined in the configuration",
+        state.services.len() as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_ports_in_use",
+        "gauge",
+        "Ports assigned to enabled services",
+        state.ports_in_use(&leases).len() as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_port_leases",
+        "gauge",
+        "Live port leases",
+        leases.len() as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_port_allocations_total",
+        "counter",
+        "Ports handed out through the allocate endpoint",
+        metrics.port_allocations as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_port_reassignments_total",
+        "counter",
+        "Allocations that could not use the preferred port",
+        metrics.port_reassignments as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_config_reloads_total",
+        "counter",
+        "Configuration reloads applied",
+        metrics.config_reloads as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_config_reload_failures_total",
+        "counter",
+        "Configuration reloads rejected",
+        metrics.config_reload_failures as f64,
+    );
+    scalar(
+        &mut out,
+        "hive_warden_uptime_seconds",
+        "gauge",
+        "Time since the warden started",
+        metrics.warden_started.elapsed().as_secs_f64(),
+    );
+
+    out
+}
@@ -0,0 +1,345 @@
+// Port leases - ports handed out through the API, with owners, expiry and persistence
+use crate::{find_available_port, is_port_in_use, settings::PortSettings, WARDEN_STATE};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    path::Path,
+    sync::Mutex,
+};
+
+/// Starts the owner of every lease the warden holds for itself, such as a replica's port
+const WARDEN_OWNER_PREFIX: &str = "warden:";
+
+lazy_static! {
+    static ref PORT_LEASES: Mutex<PortLeases> = Mutex::new(PortLeases::new(PortSettings::default()));
+}
+
+/// A port handed to an owner until it is released or its TTL lapses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortLease {
+    pub port: u16,
+    pub owner: String,
+    pub created: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// When the lease lapses unless renewed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl PortLease {
+    fn new(port: u16, owner: &str, ttl_secs: Option<u64>) -> Self {
+        let mut lease = Self {
+            port,
+            owner: owner.to_string(),
+            created: Local::now().to_rfc3339(),
+            ttl_secs,
+            expires: None,
+        };
+        lease.renew(ttl_secs);
+        lease
+    }
+
+    /// Push the expiry out by the TTL, keeping the current TTL when none is given
+    fn renew(&mut self, ttl_secs: Option<u64>) {
+        if ttl_secs.is_some() {
+            self.ttl_secs = ttl_secs;
+        }
+        self.expires = self
+            .ttl_secs
+            .map(|ttl| (Local::now() + chrono::Duration::seconds(ttl as i64)).to_rfc3339());
+    }
+
+    fn is_expired(&self, now: DateTime<Local>) -> bool {
+        self.expires
+            .as_deref()
+            .and_then(|expires| DateTime::parse_from_rfc3339(expires).ok())
+            .is_some_and(|expires| expires < now)
+    }
+}
+
+/// Result of an allocation request
+#[derive(Debug)]
+pub struct Allocation {
+    pub lease: PortLease,
+    /// The preferred port could not be used
+    pub reassigned: bool,
+}
+
+/// Why a lease operation was refused
+#[derive(Debug)]
+pub enum PortError {
+    /// The owner name can't be used through the API
+    Invalid(String),
+    NotFound(String),
+    /// The lease belongs to someone else
+    NotOwner(String),
+    /// No free port left in the range
+    Exhausted(String),
+}
+
+impl fmt::Display for PortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortError::Invalid(msg)
+            | PortError::NotFound(msg)
+            | PortError::NotOwner(msg)
+            | PortError::Exhausted(msg) => f.write_str(msg),
+        }
+    }
+}
+
+struct PortLeases {
+    settings: PortSettings,
+    leases: BTreeMap<u16, PortLease>,
+}
+
+impl PortLeases {
+    fn new(settings: PortSettings) -> Self {
+        Self {
+            settings,
+            leases: BTreeMap::new(),
+        }
+    }
+
+    /// Drop lapsed leases; returns whether any were removed
+    fn purge_expired(&mut self) -> bool {
+        let now = Local::now();
+        let before = self.leases.len();
+        self.leases.retain(|port, lease| {
+            let expired = lease.is_expired(now);
+            if expired {
+                println!("⌛ Port lease {} for {} expired", port, lease.owner);
+            }
+            !expired
+        });
+        self.leases.len() != before
+    }
+
+    fn save(&self) {
+        let path = match &self.settings.leases_file {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(e) = save_leases(path, &self.leases) {
+            eprintln!("⚠️  Failed to save port leases to {}: {}", path.display(), e);
+        }
+    }
+
+    fn insert(&mut self, lease: PortLease) -> PortLease {
+        println!("🔌 Leased port {} to {}", lease.port, lease.owner);
+        self.leases.insert(lease.port, lease.clone());
+        self.save();
+        lease
+    }
+}
+
+/// The owner of a lease the warden holds for itself under `name`
+pub fn warden_owner(name: &str) -> String {
+    format!("{}{}", WARDEN_OWNER_PREFIX, name)
+}
+
+/// The name a warden-held lease was taken under; `None` for leases taken through the API
+pub fn warden_owned(owner: &str) -> Option<&str> {
+    owner.strip_prefix(WARDEN_OWNER_PREFIX)
+}
+
+/// Check an owner given through the API
+///
+/// '@' and ':' are kept for the owners of replica and upgrade ports, so no
+/// caller can take or give back a port in the warden's name.
+pub fn validate_owner(owner: &str) -> Result<(), PortError> {
+    if owner.is_empty() || owner.contains(['@', ':']) {
+        return Err(PortError::Invalid(format!(
+            "Invalid owner {:?}: must be non-empty without '@' or ':'",
+            owner
+        )));
+    }
+    Ok(())
+}
+
+fn load_leases(path: &Path) -> Vec<PortLease> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        eprintln!("⚠️  Ignoring unreadable port leases {}: {}", path.display(), e);
+        Vec::new()
+    })
+}
+
+/// Write the leases, replacing the previous file atomically
+fn save_leases(path: &Path, leases: &BTreeMap<u16, PortLease>) -> Result<()> {
+    let leases: Vec<&PortLease> = leases.values().collect();
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&leases)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Apply port settings and restore leases saved by a previous run
+pub fn configure(settings: PortSettings) {
+    let saved = settings.leases_file.as_deref().map(load_leases).unwrap_or_default();
+    let mut leases = PORT_LEASES.lock().unwrap();
+    *leases = PortLeases::new(settings);
+    leases.leases = saved.into_iter().map(|lease| (lease.port, lease)).collect();
+    if leases.purge_expired() {
+        leases.save();
+    }
+    if !leases.leases.is_empty() {
+        println!("🔌 Restored {} port leases", leases.leases.len());
+    }
+}
+
+/// The range scanned for free ports
+pub fn range() -> (u16, u16) {
+    let leases = PORT_LEASES.lock().unwrap();
+    (leases.settings.range_start, leases.settings.range_end)
+}
+
+/// The live lease on a port, if any
+pub fn lease(port: u16) -> Option<PortLease> {
+    let leases = PORT_LEASES.lock().unwrap();
+    leases
+        .leases
+        .get(&port)
+        .filter(|lease| !lease.is_expired(Local::now()))
+        .cloned()
+}
+
+/// Every live lease, by port
+pub fn leases() -> Vec<PortLease> {
+    let mut leases = PORT_LEASES.lock().unwrap();
+    if leases.purge_expired() {
+        leases.save();
+    }
+    leases.leases.values().cloned().collect()
+}
+
+/// Remove lapsed leases and persist the result
+pub fn purge_expired() {
+    let mut leases = PORT_LEASES.lock().unwrap();
+    if leases.purge_expired() {
+        leases.save();
+    }
+}
+
+/// Lease a port to `owner`, preferring `preferred` when it is free
+///
+/// Asking again for a port the owner already holds renews that lease. Ports
+/// leased to others, assigned to other configured services, or bound on the
+/// system are skipped.
+pub fn allocate(owner: &str, preferred: Option<u16>, ttl_secs: Option<u64>) -> Result<Allocation, PortError> {
+    allocate_lease(owner, preferred, ttl_secs, true)
+}
+
+/// The port leased to `owner`, leasing one that never lapses if it has none
+///
+/// For ports the warden hands out itself, such as those of service replicas.
+pub fn reserve(owner: &str) -> Result<PortLease, PortError> {
+    if let Some(lease) = leases().into_iter().find(|lease| lease.owner == owner) {
+        return Ok(lease);
+    }
+    allocate_lease(owner, None, None, false).map(|allocation| allocation.lease)
+}
+
+fn allocate_lease(
+    owner: &str,
+    preferred: Option<u16>,
+    ttl_secs: Option<u64>,
+    default_ttl: bool,
+) -> Result<Allocation, PortError> {
+    let service_ports: HashSet<u16> = WARDEN_STATE.lock().unwrap()
+        .services
+        .values()
+        .filter(|s| s.name != owner)
+        .map(|s| s.port)
+        .collect();
+
+    let mut leases = PORT_LEASES.lock().unwrap();
+    leases.purge_expired();
+    let ttl_secs = if default_ttl {
+        ttl_secs.or(leases.settings.default_lease_ttl_secs)
+    } else {
+        ttl_secs
+    };
+
+    if let Some(port) = preferred {
+        match leases.leases.get_mut(&port) {
+            Some(lease) if lease.owner == owner => {
+                lease.renew(ttl_secs);
+                let lease = lease.clone();
+                leases.save();
+                return Ok(Allocation { lease, reassigned: false });
+            }
+            Some(_) => {}
+            None if !service_ports.contains(&port) && !is_port_in_use(port) => {
+                let lease = leases.insert(PortLease::new(port, owner, ttl_secs));
+                return Ok(Allocation { lease, reassigned: false });
+            }
+            None => {}
+        }
+    }
+
+    let mut reserved = service_ports;
+    reserved.extend(leases.leases.keys().copied());
+    let (start, end) = (leases.settings.range_start, leases.settings.range_end);
+    let port = find_available_port(start, end, &reserved)
+        .ok_or_else(|| PortError::Exhausted(format!("No available ports in {}-{}", start, end)))?;
+
+    let lease = leases.insert(PortLease::new(port, owner, ttl_secs));
+    Ok(Allocation {
+        lease,
+        reassigned: preferred.is_some(),
+    })
+}
+
+fn owned_lease<'a>(leases: &'a mut PortLeases, owner: &str, port: u16) -> Result<&'a mut PortLease, PortError> {
+    leases.purge_expired();
+    match leases.leases.get_mut(&port) {
+        None => Err(PortError::NotFound(format!("Port {} is not leased", port))),
+        Some(lease) if lease.owner != owner => Err(PortError::NotOwner(format!(
+            "Port {} is leased to {}",
+            port, lease.owner
+        ))),
+        Some(lease) => Ok(lease),
+    }
+}
+
+/// Extend a lease; a new TTL replaces the old one
+pub fn renew(owner: &str, port: u16, ttl_secs: Option<u64>) -> Result<PortLease, PortError> {
+    let mut leases = PORT_LEASES.lock().unwrap();
+    let lease = owned_lease(&mut leases, owner, port)?;
+    lease.renew(ttl_secs);
+    let lease = lease.clone();
+    leases.save();
+    Ok(lease)
+}
+
+/// Give a leased port back
+pub fn release(owner: &str, port: u16) -> Result<PortLease, PortError> {
+    let mut leases = PORT_LEASES.lock().unwrap();
+    owned_lease(&mut leases, owner, port)?;
+    let lease = leases.leases.remove(&port).expect("lease checked above");
+    leases.save();
+    println!("🔌 Released port {} from {}", port, owner);
+    Ok(lease)
+}
+
+/// Hand a lease over to a new owner; it no longer lapses
+pub fn transfer(owner: &str, port: u16, new_owner: &str) -> Result<PortLease, PortError> {
+    let mut leases = PORT_LEASES.lock().unwrap();
+    let lease = owned_lease(&mut leases, owner, port)?;
+    lease.owner = new_owner.to_string();
+    lease.ttl_secs = None;
+    lease.expires = None;
+    let lease = lease.clone();
+    leases.save();
+    println!("🔌 Port {} passed from {} to {}", port, owner, new_owner);
+    Ok(lease)
+}
@@ -0,0 +1,372 @@
+// Service processes - spawned children, processes re-adopted after a warden restart,
+// and the records in the runtime directory that make re-adoption possible
+use crate::STOP_POLL_INTERVAL;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::PathBuf,
+    process::{Child, ExitStatus},
+    sync::RwLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref RUNTIME_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// A service process the warden is responsible for
+pub enum ServiceProcess {
+    /// Spawned by this warden
+    Child(Child),
+    /// Left running by a previous warden; not our child, so it can only be watched
+    Adopted(AdoptedProcess),
+}
+
+pub struct AdoptedProcess {
+    pid: u32,
+    start_time: u64,
+}
+
+/// How a process ended
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessExit {
+    pub code: Option<i32>,
+    /// Terminating signal on Unix
+    pub signal: Option<i32>,
+    /// An adopted process's exit status can't be collected
+    pub known: bool,
+}
+
+impl ProcessExit {
+    fn from_status(status: ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal()
+        };
+        #[cfg(not(unix))]
+        let signal = None;
+        Self {
+            code: status.code(),
+            signal,
+            known: true,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Human-readable description of how the process exited
+    pub fn describe(&self) -> String {
+        match (self.signal, self.code) {
+            _ if !self.known => "exited (status unknown, process was adopted)".to_string(),
+            (Some(signal), _) => format!("killed by signal {}", signal),
+            (None, Some(code)) => format!("exited with code {}", code),
+            (None, None) => "exited".to_string(),
+        }
+    }
+}
+
+impl ServiceProcess {
+    pub fn id(&self) -> u32 {
+        match self {
+            ServiceProcess::Child(child) => child.id(),
+            ServiceProcess::Adopted(adopted) => adopted.pid,
+        }
+    }
+
+    /// `Ok(None)` while the process is running
+    pub fn try_wait(&mut self) -> io::Result<Option<ProcessExit>> {
+        match self {
+            ServiceProcess::Child(child) => Ok(child.try_wait()?.map(ProcessExit::from_status)),
+            ServiceProcess::Adopted(adopted) => {
+                if start_time(adopted.pid) == Some(adopted.start_time) {
+                    Ok(None)
+                } else {
+                    Ok(Some(ProcessExit {
+                        code: None,
+                        signal: None,
+                        known: false,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+/// What the runtime directory remembers about a running service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessRecord {
+    pub pid: u32,
+    /// Kernel start time of the process, so a reused pid isn't mistaken for the service
+    pub start_time: u64,
+    pub port: u16,
+    pub started: String,
+}
+
+/// Set the directory for process records and output FIFOs
+pub fn configure(runtime_dir: Option<PathBuf>) {
+    if let Some(dir) = &runtime_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("⚠️  Failed to create runtime directory {}: {}", dir.display(), e);
+        }
+    }
+    *RUNTIME_DIR.write().unwrap() = runtime_dir;
+}
+
+fn runtime_path(service_name: &str, suffix: &str) -> Option<PathBuf> {
+    RUNTIME_DIR
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|dir| dir.join(format!("{}.{}", service_name, suffix)))
+}
+
+/// Kernel start time of a process, in clock ticks since boot
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses; fields resume after the last ')'
+    let after_name = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = after_name.split_whitespace().collect();
+    // Field 22 of stat(5); `state` (field 3) is the first after the name.
+    // A zombie has exited already.
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    fields.get(19)?.parse().ok()
+}
+
+/// Without /proc there is no fingerprint, so nothing is ever re-adopted
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Remember a freshly spawned process so a restarted warden can find it
+pub fn record(service_name: &str, pid: u32, port: u16) {
+    let (path, start_time) = match (runtime_path(service_name, "pid.json"), start_time(pid)) {
+        (Some(path), Some(start_time)) => (path, start_time),
+        _ => return,
+    };
+    let record = ProcessRecord {
+        pid,
+        start_time,
+        port,
+        started: chrono::Local::now().to_rfc3339(),
+    };
+    let tmp = path.with_extension("json.tmp");
+    let written = serde_json::to_string_pretty(&record)
+        .map_err(io::Error::other)
+        .and_then(|json| fs::write(&tmp, json))
+        .and_then(|_| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        eprintln!("⚠️  Failed to record pid for {}: {}", service_name, e);
+    }
+}
+
+/// The service's process is gone; drop its record
+pub fn forget(service_name: &str) {
+    if let Some(path) = runtime_path(service_name, "pid.json") {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Every record left in the runtime directory, by service name
+pub fn records() -> Vec<(String, ProcessRecord)> {
+    let dir = match RUNTIME_DIR.read().unwrap().clone() {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.strip_suffix(".pid.json")?.to_string();
+            let record = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+            Some((name, record))
+        })
+        .collect()
+}
+
+/// Adopt a recorded process if it is still the same process
+pub fn adopt(record: &ProcessRecord) -> Option<ServiceProcess> {
+    (start_time(record.pid) == Some(record.start_time)).then(|| {
+        ServiceProcess::Adopted(AdoptedProcess {
+            pid: record.pid,
+            start_time: record.start_time,
+        })
+    })
+}
+
+/// Output FIFOs that outlive the warden, so a service keeps writing while it restarts
+///
+/// Each FIFO is opened read-write for the child: it then never sees a closed
+/// pipe, and output written while the warden is down waits in the FIFO.
+#[cfg(unix)]
+pub mod fifo {
+    use super::runtime_path;
+    use crate::logs::LogStream;
+    use std::{
+        ffi::CString,
+        fs::{self, File, OpenOptions},
+        io,
+        os::unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+        path::{Path, PathBuf},
+    };
+
+    /// Room for output written while nobody is reading
+    #[cfg(target_os = "linux")]
+    const FIFO_CAPACITY: libc::c_int = 1024 * 1024;
+
+    pub fn path(service_name: &str, stream: LogStream) -> Option<PathBuf> {
+        runtime_path(service_name, stream.as_str())
+    }
+
+    fn create(path: &Path) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: c_path is a valid NUL-terminated path
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// The child's end of a fresh FIFO, and the warden's end to read from
+    pub fn open_pair(path: &Path) -> io::Result<(File, File)> {
+        // Anything left from a previous process would be mixed into the new one's output
+        let _ = fs::remove_file(path);
+        create(path)?;
+        let child_end = OpenOptions::new().read(true).write(true).open(path)?;
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            // SAFETY: fcntl on a FIFO we just opened; failure only leaves the default size
+            unsafe { libc::fcntl(child_end.as_raw_fd(), libc::F_SETPIPE_SZ, FIFO_CAPACITY) };
+        }
+        let reader = open_reader(path)?;
+        Ok((child_end, reader))
+    }
+
+    /// Reopen an existing FIFO to read an adopted service's output
+    pub fn open_reader(path: &Path) -> io::Result<File> {
+        use std::os::unix::io::AsRawFd;
+        // Non-blocking so the open doesn't wait for a writer, then blocking reads
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        // SAFETY: fcntl on a descriptor we own
+        unsafe {
+            let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+        Ok(file)
+    }
+
+    pub fn remove(service_name: &str) {
+        for stream in [LogStream::Stdout, LogStream::Stderr] {
+            if let Some(path) = path(service_name, stream) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn aside_path(service_name: &str, stream: LogStream) -> Option<PathBuf> {
+        runtime_path(service_name, &format!("{}.previous", stream.as_str()))
+    }
+
+    /// Move a running service's FIFOs out of the way while a second process for it starts
+    ///
+    /// The running process keeps writing to them and the warden keeps reading.
+    pub fn set_aside(service_name: &str) {
+        for stream in [LogStream::Stdout, LogStream::Stderr] {
+            if let (Some(path), Some(aside)) = (path(service_name, stream), aside_path(service_name, stream)) {
+                let _ = fs::rename(path, aside);
+            }
+        }
+    }
+
+    /// Put FIFOs set aside back, for the process they belong to
+    pub fn restore(service_name: &str) {
+        for stream in [LogStream::Stdout, LogStream::Stderr] {
+            if let (Some(path), Some(aside)) = (path(service_name, stream), aside_path(service_name, stream)) {
+                let _ = fs::rename(aside, path);
+            }
+        }
+    }
+
+    /// Remove FIFOs set aside once their process is gone
+    pub fn discard(service_name: &str) {
+        for stream in [LogStream::Stdout, LogStream::Stderr] {
+            if let Some(aside) = aside_path(service_name, stream) {
+                let _ = fs::remove_file(aside);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_process_group(pgid: u32, signal: libc::c_int) -> bool {
+    // SAFETY: kill(2) with a negative pid only signals the given process group
+    unsafe { libc::kill(-(pgid as libc::pid_t), signal) == 0 }
+}
+
+/// Terminate a service gracefully, escalating to SIGKILL after the grace period
+#[cfg(unix)]
+pub fn terminate(service_name: &str, process: &mut ServiceProcess, grace: Duration) -> Result<()> {
+    // Services are spawned as process group leaders, so the pid is the pgid
+    let pgid = process.id();
+    // Signalled even when the leader has exited, as what it spawned may still be running
+    let _ = process.try_wait()?;
+    if !signal_process_group(pgid, libc::SIGTERM) {
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        // Reap the leader as soon as it exits, then wait for the rest of the group
+        let _ = process.try_wait()?;
+        if !signal_process_group(pgid, 0) {
+            return Ok(());
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+
+    println!(
+        "⚠️  {} did not exit within {}ms, sending SIGKILL",
+        service_name,
+        grace.as_millis()
+    );
+    signal_process_group(pgid, libc::SIGKILL);
+    match process {
+        ServiceProcess::Child(child) => {
+            child.wait()?;
+        }
+        ServiceProcess::Adopted(_) => {
+            while process.try_wait()?.is_none() {
+                thread::sleep(STOP_POLL_INTERVAL);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Terminate a service; only a forced kill is available off Unix
+#[cfg(not(unix))]
+pub fn terminate(_service_name: &str, process: &mut ServiceProcess, _grace: Duration) -> Result<()> {
+    if let ServiceProcess::Child(child) = process {
+        if child.try_wait()?.is_none() {
+            child.kill()?;
+        }
+        child.wait()?;
+    }
+    Ok(())
+}
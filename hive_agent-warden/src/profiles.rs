@@ -0,0 +1,218 @@
+// Service profiles - named subsets of the services, activated as a whole
+use crate::{
+    events::{self, EventKind},
+    persist_state,
+    reload::CONFIG_LOCK,
+    replicas,
+    start_services_in_order, stop_service, update_runtime, WardenState, HEALTH_CHECK_FAILURES,
+    RESTART_TRACKERS, WARDEN_STATE,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    sync::RwLock,
+    thread,
+};
+
+lazy_static! {
+    static ref PROFILES: RwLock<BTreeMap<String, Vec<String>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Why a profile could not be activated
+#[derive(Debug)]
+pub enum ProfileError {
+    NotFound(String),
+    /// The profile doesn't fit the current service definitions
+    Invalid(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::NotFound(msg) | ProfileError::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// A profile as reported by the API
+#[derive(Debug, Serialize)]
+pub struct ProfileView {
+    pub name: String,
+    pub services: Vec<String>,
+    pub active: bool,
+}
+
+/// What an activation switched
+#[derive(Debug, Serialize)]
+pub struct Activation {
+    pub profile: String,
+    /// Services that were disabled before and are now enabled
+    pub enabled: Vec<String>,
+    /// Services that were enabled before and are now stopped and disabled
+    pub disabled: Vec<String>,
+}
+
+/// Set the profiles from the warden settings
+pub fn configure(profiles: BTreeMap<String, Vec<String>>) {
+    if !profiles.is_empty() {
+        let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        println!("🗂️  Service profiles: {}", names.join(", "));
+    }
+    *PROFILES.write().unwrap() = profiles;
+}
+
+/// The last activated profile, as long as the enabled services still match it
+///
+/// Enabling or disabling a service by hand afterwards means no profile is active.
+pub fn active(state: &WardenState) -> Option<String> {
+    let name = state.active_profile.as_ref()?;
+    let profiles = PROFILES.read().unwrap();
+    let members: HashSet<&str> = profiles
+        .get(name)?
+        .iter()
+        .map(String::as_str)
+        .filter(|member| *member != "hive_agent-warden")
+        .collect();
+    let enabled: HashSet<&str> = state
+        .services
+        .keys()
+        .map(String::as_str)
+        .filter(|service| *service != "hive_agent-warden" && state.is_enabled(service))
+        .collect();
+    (enabled == members).then(|| name.clone())
+}
+
+/// Every configured profile with its services
+pub fn list(state: &WardenState) -> Vec<ProfileView> {
+    let active = active(state);
+    PROFILES
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(name, services)| ProfileView {
+            name: name.clone(),
+            services: services.clone(),
+            active: active.as_deref() == Some(name),
+        })
+        .collect()
+}
+
+/// Check that a profile only names known services and carries their dependencies
+fn validate(name: &str, members: &[String], state: &WardenState) -> Result<(), ProfileError> {
+    let member_set: HashSet<&str> = members.iter().map(String::as_str).collect();
+    for member in members {
+        let service = state.services.get(member).ok_or_else(|| {
+            ProfileError::Invalid(format!("Profile {} lists unknown service {}", name, member))
+        })?;
+        if let Some(dep) = service.depends_on.iter().find(|dep| !member_set.contains(dep.as_str())) {
+            return Err(ProfileError::Invalid(format!(
+                "Profile {}: {} depends on {}, which the profile leaves out",
+                name, member, dep
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Warn about profiles that no longer fit the service definitions
+///
+/// Run whenever the definitions are loaded, so a typo shows up at startup
+/// rather than when the profile is activated; activation still refuses them.
+pub fn check(state: &WardenState) {
+    for (name, members) in PROFILES.read().unwrap().iter() {
+        if let Err(e) = validate(name, members, state) {
+            eprintln!("⚠️  {}", e);
+        }
+    }
+}
+
+/// Enable exactly the services of a profile and disable the rest
+///
+/// Services leaving the set are stopped in reverse dependency order before this
+/// returns; the profile's services are then started in dependency order in the
+/// background, each once its dependencies are healthy. Services that are
+/// already enabled keep running untouched.
+pub fn activate(name: &str) -> Result<Activation, ProfileError> {
+    // Service definitions can't change underneath an activation
+    let _guard = CONFIG_LOCK.lock().unwrap();
+
+    let members = PROFILES
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| ProfileError::NotFound(format!("Profile {} not found", name)))?;
+
+    let (order, to_enable, to_disable) = {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        validate(name, &members, &state)?;
+        let order: Vec<String> = state
+            .start_order
+            .iter()
+            .filter(|service| service.as_str() != "hive_agent-warden")
+            .cloned()
+            .collect();
+        let (to_enable, to_disable): (Vec<String>, Vec<String>) = order
+            .iter()
+            .filter(|service| members.contains(service) != state.is_enabled(service))
+            .cloned()
+            .partition(|service| members.contains(service));
+
+        state.active_profile = Some(name.to_string());
+        (order, to_enable, to_disable)
+    };
+
+    println!(
+        "🗂️  Activating profile {}: enabling {:?}, disabling {:?}",
+        name, to_enable, to_disable
+    );
+
+    // Dependents go down before the services they depend on
+    for service in to_disable.iter().rev() {
+        update_runtime(service, |runtime| {
+            runtime.enabled = false;
+            runtime.mark_stopped();
+        });
+        if let Err(e) = stop_service(service) {
+            eprintln!("❌ Failed to stop {}: {}", service, e);
+        }
+    }
+    // Members that had failed get another go, as with a plain enable
+    let to_start: Vec<String> = order.into_iter().filter(|service| members.contains(service)).collect();
+    for service in &to_start {
+        let retry = update_runtime(service, |runtime| {
+            let retry = !runtime.enabled || runtime.failed;
+            runtime.enabled = true;
+            runtime.failed = false;
+            runtime.failure_reason = None;
+            retry
+        });
+        if retry == Some(true) {
+            RESTART_TRACKERS.lock().unwrap().remove(service);
+            HEALTH_CHECK_FAILURES.lock().unwrap().remove(service);
+            replicas::reset(service);
+        }
+    }
+
+    if let Err(e) = persist_state() {
+        eprintln!("Failed to persist state: {}", e);
+    }
+
+    thread::spawn(move || start_services_in_order(&to_start));
+
+    events::emit(
+        None,
+        EventKind::ProfileActivated {
+            profile: name.to_string(),
+            enabled: to_enable.clone(),
+            disabled: to_disable.clone(),
+        },
+    );
+    Ok(Activation {
+        profile: name.to_string(),
+        enabled: to_enable,
+        disabled: to_disable,
+    })
+}
@@ -0,0 +1,138 @@
+// Reverse proxy - a stable `/{service}/...` address spread round-robin over the replicas that are up
+use crate::{auth, replicas};
+use actix_web::{
+    http::{header, StatusCode},
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use lazy_static::lazy_static;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// Largest request body passed on; bodies are read in full so a refused connection can be retried
+pub const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+/// A replica that doesn't accept the connection by then is skipped for the next one
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Headers about one connection rather than the request, never forwarded (RFC 9110 7.6.1)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+lazy_static! {
+    // No overall timeout so streamed responses stay open; redirects go back to the caller
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy()
+        .build()
+        .expect("proxy HTTP client");
+    /// Round-robin position per service
+    static ref NEXT_REPLICA: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+fn forwarded(name: &str) -> bool {
+    !HOP_BY_HOP.contains(&name) && name != "host" && name != "content-length"
+}
+
+fn error_response(status: StatusCode, message: String) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({
+        "status": "error",
+        "message": message
+    }))
+}
+
+async fn send(req: &HttpRequest, url: &str, service: &str, body: Bytes) -> reqwest::Result<reqwest::Response> {
+    let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes()).unwrap_or(reqwest::Method::GET);
+    let mut request = CLIENT.request(method, url);
+    // A warden token is for the warden; a service could otherwise reuse it against the API
+    let strip_authorization = auth::carries_warden_token(req.headers());
+    for (name, value) in req.headers() {
+        if forwarded(name.as_str()) && !(strip_authorization && name == header::AUTHORIZATION) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+
+    let client_ip = req.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string());
+    let (host, scheme) = {
+        let info = req.connection_info();
+        (info.host().to_string(), info.scheme().to_string())
+    };
+    let forwarded_for = match req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+        Some(earlier) => format!("{}, {}", earlier, client_ip),
+        None => client_ip,
+    };
+    request
+        .header("x-forwarded-for", forwarded_for)
+        .header("x-forwarded-host", host)
+        .header("x-forwarded-proto", scheme)
+        .header("x-forwarded-prefix", format!("/{}", service))
+        .body(body)
+        .send()
+        .await
+}
+
+/// Pass a replica's answer back, streaming the body as it arrives
+fn relay(response: reqwest::Response, unit: &str) -> HttpResponse {
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut builder = HttpResponse::build(status);
+    for (name, value) in response.headers() {
+        if forwarded(name.as_str()) {
+            builder.append_header((name.as_str(), value.as_bytes()));
+        }
+    }
+    builder.insert_header(("x-warden-replica", unit));
+    builder.streaming(response.bytes_stream())
+}
+
+/// Forward a request for `/{service}/{path}` to the next replica of the service that is up
+///
+/// Replicas that are starting, unhealthy or failed are out of rotation; one that refuses
+/// the connection is skipped for the next. Returns `None` for an unknown service.
+pub async fn forward(service: &str, path: &str, req: &HttpRequest, body: Bytes) -> Option<HttpResponse> {
+    let units = replicas::up_units(service)?;
+    if units.is_empty() {
+        return Some(error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("No healthy replica of {}", service),
+        ));
+    }
+
+    let start = {
+        let mut next = NEXT_REPLICA.lock().unwrap();
+        let position = next.entry(service.to_string()).or_insert(0);
+        *position = position.wrapping_add(1);
+        *position
+    };
+    let query = match req.query_string() {
+        "" => String::new(),
+        query => format!("?{}", query),
+    };
+
+    for attempt in 0..units.len() {
+        let (unit, port) = &units[(start + attempt) % units.len()];
+        let url = format!("http://127.0.0.1:{}/{}{}", port, path, query);
+        match send(req, &url, service, body.clone()).await {
+            Ok(response) => return Some(relay(response, unit)),
+            Err(e) if e.is_connect() => {
+                eprintln!("⚠️  Proxy could not reach {}: {}", unit, e);
+            }
+            Err(e) => {
+                return Some(error_response(
+                    StatusCode::BAD_GATEWAY,
+                    format!("{} did not answer: {}", unit, e),
+                ));
+            }
+        }
+    }
+    Some(error_response(
+        StatusCode::BAD_GATEWAY,
+        format!("No replica of {} accepted the connection", service),
+    ))
+}
@@ -0,0 +1,162 @@
+// Service registry - add and remove service definitions at runtime through the API
+use crate::{
+    dependency_order, is_port_in_use, load_services_config, persist_state, ports, reload::CONFIG_LOCK,
+    replicas, runtime::ServiceRuntime, save_services_config, start_services_in_order, stats,
+    stop_service, validate_name, validate_services, ServiceConfig, CONFIG_PATH, HEALTH_CHECK_FAILURES,
+    RESTART_TRACKERS, WARDEN_STATE,
+};
+use std::{fmt, thread};
+
+/// Why a registration or removal was refused
+#[derive(Debug)]
+pub enum RegistryError {
+    /// The request itself is malformed
+    Invalid(String),
+    /// The request clashes with an existing service or port
+    Conflict(String),
+    NotFound(String),
+    /// The config file could not be updated
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Invalid(msg)
+            | RegistryError::Conflict(msg)
+            | RegistryError::NotFound(msg) => f.write_str(msg),
+            RegistryError::Failed(e) => write!(f, "Failed to update service definitions: {}", e),
+        }
+    }
+}
+
+/// Why a service's port can't be handed to it: leased to someone else or bound outside the warden
+pub fn port_taken(service: &ServiceConfig) -> Option<String> {
+    if let Some(lease) = ports::lease(service.port).filter(|lease| lease.owner != service.name) {
+        return Some(format!("Port {} is leased to {}", service.port, lease.owner));
+    }
+    is_port_in_use(service.port).then(|| format!("Port {} is already in use", service.port))
+}
+
+/// Add a service definition, persist it to the config file, and start it if enabled
+pub fn register_service(service: ServiceConfig) -> Result<(), RegistryError> {
+    let _guard = CONFIG_LOCK.lock().unwrap();
+    validate_name(&service.name).map_err(|e| RegistryError::Invalid(e.to_string()))?;
+
+    let mut services: Vec<ServiceConfig> = {
+        let state = WARDEN_STATE.lock().unwrap();
+        if state.services.contains_key(&service.name) {
+            return Err(RegistryError::Conflict(format!("Service {} already exists", service.name)));
+        }
+        if let Some(owner) = state.services.values().find(|s| s.port == service.port) {
+            return Err(RegistryError::Conflict(format!(
+                "Port {} is already assigned to {}",
+                service.port, owner.name
+            )));
+        }
+        state.services.values().cloned().collect()
+    };
+    if let Some(reason) = port_taken(&service) {
+        return Err(RegistryError::Conflict(reason));
+    }
+
+    services.push(service.clone());
+    validate_services(&services).map_err(|e| RegistryError::Invalid(e.to_string()))?;
+    let start_order = dependency_order(&services).map_err(|e| RegistryError::Invalid(e.to_string()))?;
+
+    // Edit the file as it is on disk so unapplied changes to other entries survive
+    let config_path = CONFIG_PATH.read().unwrap().clone();
+    let mut on_disk = load_services_config(&config_path).map_err(RegistryError::Failed)?;
+    if on_disk.iter().any(|s| s.name == service.name) {
+        return Err(RegistryError::Conflict(format!(
+            "Service {} is already in {}; reload the config instead",
+            service.name,
+            config_path.display()
+        )));
+    }
+    on_disk.push(service.clone());
+    save_services_config(&config_path, &on_disk).map_err(RegistryError::Failed)?;
+
+    {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        state.runtime.insert(service.name.clone(), ServiceRuntime::new(service.enabled));
+        state.services.insert(service.name.clone(), service.clone());
+        state.start_order = start_order;
+    }
+    if let Err(e) = persist_state() {
+        eprintln!("Failed to persist state: {}", e);
+    }
+
+    println!("➕ Registered service: {} on port {}", service.name, service.port);
+    if service.enabled {
+        let names = vec![service.name];
+        thread::spawn(move || start_services_in_order(&names));
+    }
+    Ok(())
+}
+
+/// Stop a service and remove its definition from the config file
+///
+/// Services that others depend on are refused; remove the dependents first.
+pub fn unregister_service(name: &str) -> Result<ServiceConfig, RegistryError> {
+    let _guard = CONFIG_LOCK.lock().unwrap();
+
+    if name == "hive_agent-warden" {
+        return Err(RegistryError::Invalid("The warden cannot remove itself".to_string()));
+    }
+
+    let service = {
+        let state = WARDEN_STATE.lock().unwrap();
+        let service = state
+            .services
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RegistryError::NotFound(format!("Service {} not found", name)))?;
+
+        let mut dependents: Vec<&str> = state
+            .services
+            .values()
+            .filter(|s| s.depends_on.iter().any(|dep| dep == name))
+            .map(|s| s.name.as_str())
+            .collect();
+        if !dependents.is_empty() {
+            dependents.sort();
+            return Err(RegistryError::Conflict(format!(
+                "Service {} is a dependency of {}",
+                name,
+                dependents.join(", ")
+            )));
+        }
+        service
+    };
+
+    let config_path = CONFIG_PATH.read().unwrap().clone();
+    let mut on_disk = load_services_config(&config_path).map_err(RegistryError::Failed)?;
+    on_disk.retain(|s| s.name != name);
+    save_services_config(&config_path, &on_disk).map_err(RegistryError::Failed)?;
+
+    // Keep the monitor from restarting it while it stops
+    if let Some(runtime) = WARDEN_STATE.lock().unwrap().runtime.get_mut(name) {
+        runtime.enabled = false;
+    }
+    if let Err(e) = stop_service(name) {
+        eprintln!("❌ Failed to stop {}: {}", name, e);
+    }
+    RESTART_TRACKERS.lock().unwrap().remove(name);
+    HEALTH_CHECK_FAILURES.lock().unwrap().remove(name);
+    stats::remove(name);
+    replicas::forget(name);
+
+    {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        state.services.remove(name);
+        state.runtime.remove(name);
+        state.start_order.retain(|n| n != name);
+    }
+    if let Err(e) = persist_state() {
+        eprintln!("Failed to persist state: {}", e);
+    }
+
+    println!("➖ Removed service: {}", name);
+    Ok(service)
+}
@@ -0,0 +1,267 @@
+// Config reload - re-read core_microservices.json and apply only what changed
+use crate::{
+    dependency_order,
+    events::{self, EventKind},
+    load_services_config, metrics, profiles, registry, replicas, runtime::ServiceRuntime, start_services_in_order,
+    stats, stop_service, validate_services, ServiceConfig, CONFIG_PATH, HEALTH_CHECK_FAILURES,
+    RESTART_TRACKERS, WARDEN_STATE,
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Pause after noticing a change so an editor's write has finished
+const CONFIG_SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    /// Only one change to the service definitions (reload, register, unregister) is applied at a time
+    pub static ref CONFIG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// How a freshly loaded configuration differs from the running set
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare the running definitions against a new set
+pub fn diff_services(current: &HashMap<String, ServiceConfig>, desired: &[ServiceConfig]) -> ConfigDiff {
+    let mut diff = ConfigDiff::default();
+    let desired_names: HashSet<&str> = desired.iter().map(|s| s.name.as_str()).collect();
+
+    for service in desired {
+        match current.get(&service.name) {
+            None => diff.added.push(service.name.clone()),
+            Some(existing) if existing != service => diff.changed.push(service.name.clone()),
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    diff.removed = current
+        .keys()
+        .filter(|name| !desired_names.contains(name.as_str()))
+        .cloned()
+        .collect();
+
+    diff.added.sort();
+    diff.changed.sort();
+    diff.removed.sort();
+    diff
+}
+
+/// Re-read the config file and apply the difference to the running services
+///
+/// Removed services are stopped, added ones started, and changed ones
+/// restarted with their new definition. Unchanged services are not touched.
+/// An invalid config is rejected before anything is stopped.
+pub fn reload_config() -> Result<ConfigDiff> {
+    let result = apply_config_file();
+    metrics::record_reload(result.is_ok());
+    if let Ok(diff) = &result {
+        if !diff.is_empty() {
+            events::emit(
+                None,
+                EventKind::ConfigReloaded {
+                    added: diff.added.clone(),
+                    removed: diff.removed.clone(),
+                    changed: diff.changed.clone(),
+                },
+            );
+        }
+    }
+    result
+}
+
+fn apply_config_file() -> Result<ConfigDiff> {
+    let _guard = CONFIG_LOCK.lock().unwrap();
+
+    let config_path = CONFIG_PATH.read().unwrap().clone();
+    let services = load_services_config(&config_path)?;
+    validate_services(&services)?;
+    let start_order = dependency_order(&services)?;
+
+    let (diff, old_order) = {
+        let state = WARDEN_STATE.lock().unwrap();
+        // Ports the running set already holds are freed by the reload itself;
+        // any other port gets the same checks as a registration
+        for service in &services {
+            if state.services.values().any(|s| s.port == service.port) {
+                continue;
+            }
+            if let Some(reason) = registry::port_taken(service) {
+                return Err(anyhow::anyhow!("{}: {}", service.name, reason));
+            }
+        }
+        (diff_services(&state.services, &services), state.start_order.clone())
+    };
+    if diff.is_empty() {
+        return Ok(diff);
+    }
+
+    println!(
+        "🔁 Reloading config: {} added, {} removed, {} changed",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+
+    let outgoing: HashSet<&String> = diff.removed.iter().chain(&diff.changed).collect();
+
+    // Keep the monitor from restarting outgoing services while they stop,
+    // remembering whether each one was enabled
+    let mut was_enabled: HashMap<String, bool> = HashMap::new();
+    {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        for name in &outgoing {
+            if let Some(runtime) = state.runtime.get_mut(*name) {
+                was_enabled.insert((*name).clone(), runtime.enabled);
+                runtime.enabled = false;
+            }
+        }
+    }
+
+    // Dependents go down before the services they depend on
+    for name in old_order.iter().rev().filter(|name| outgoing.contains(name)) {
+        if let Err(e) = stop_service(name) {
+            eprintln!("❌ Failed to stop {}: {}", name, e);
+        }
+        RESTART_TRACKERS.lock().unwrap().remove(name);
+        HEALTH_CHECK_FAILURES.lock().unwrap().remove(name);
+        stats::remove(name);
+        replicas::forget(name);
+    }
+
+    {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        let incoming: HashMap<String, ServiceConfig> = services
+            .into_iter()
+            .map(|s| (s.name.clone(), s))
+            .collect();
+
+        for name in &diff.removed {
+            state.services.remove(name);
+            state.runtime.remove(name);
+        }
+
+        for name in diff.changed.iter().chain(&diff.added) {
+            let service = incoming[name].clone();
+            let old = state.services.get(name).cloned();
+
+            // Keep an enable/disable decision unless the definition's own flag changed
+            let enabled = match (&old, was_enabled.get(name)) {
+                (Some(old), Some(&enabled)) if old.enabled == service.enabled => enabled,
+                _ => service.enabled,
+            };
+
+            state.runtime.insert(name.clone(), ServiceRuntime::new(enabled));
+            state.services.insert(name.clone(), service);
+        }
+
+        state.start_order = start_order.clone();
+        profiles::check(&state);
+    }
+
+    let to_start: Vec<String> = start_order
+        .into_iter()
+        .filter(|name| diff.added.contains(name) || diff.changed.contains(name))
+        .collect();
+    thread::spawn(move || start_services_in_order(&to_start));
+
+    Ok(diff)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reload the config whenever the file changes on disk
+pub fn watch_config_file() {
+    thread::spawn(|| {
+        let config_path = CONFIG_PATH.read().unwrap().clone();
+        let mut last_seen = modified_time(&config_path);
+        println!("👀 Watching {} for changes", config_path.display());
+
+        loop {
+            thread::sleep(CONFIG_WATCH_INTERVAL);
+
+            let modified = modified_time(&config_path);
+            if modified.is_none() || modified == last_seen {
+                continue;
+            }
+
+            thread::sleep(CONFIG_SETTLE_DELAY);
+            last_seen = modified_time(&config_path);
+
+            match reload_config() {
+                Ok(diff) if diff.is_empty() => println!("🔁 Config file changed; nothing to apply"),
+                Ok(_) => {}
+                Err(e) => eprintln!("❌ Config reload rejected, keeping current services: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_services;
+    use crate::{tests::service, ServiceConfig};
+    use std::collections::HashMap;
+
+    fn running(services: &[ServiceConfig]) -> HashMap<String, ServiceConfig> {
+        services.iter().map(|s| (s.name.clone(), s.clone())).collect()
+    }
+
+    #[test]
+    fn services_are_sorted_into_added_removed_changed_and_unchanged() {
+        let current = running(&[
+            service("kept", 6001, &[]),
+            service("moved", 6002, &[]),
+            service("dropped", 6003, &[]),
+        ]);
+        let desired = [
+            service("kept", 6001, &[]),
+            service("moved", 6012, &[]),
+            service("new", 6004, &["kept"]),
+        ];
+
+        let diff = diff_services(&current, &desired);
+        assert_eq!(diff.added, ["new"]);
+        assert_eq!(diff.removed, ["dropped"]);
+        assert_eq!(diff.changed, ["moved"]);
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn any_field_change_counts_as_changed() {
+        let current = running(&[service("a", 6001, &[])]);
+        let mut edited = service("a", 6001, &[]);
+        edited.version = "2.0.0".to_string();
+
+        assert_eq!(diff_services(&current, &[edited]).changed, ["a"]);
+    }
+
+    #[test]
+    fn an_identical_config_is_empty() {
+        let services = [service("a", 6001, &[]), service("b", 6002, &["a"])];
+        let diff = diff_services(&running(&services), &services);
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, 2);
+    }
+}
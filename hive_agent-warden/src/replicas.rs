@@ -0,0 +1,312 @@
+// Replicas - extra instances of a service on leased ports, each supervised as its own unit
+//
+// Replica 0 is the service itself on its configured port. Replicas 1.. are named
+// `{service}@{index}` and run on ports leased from the warden's range, so the
+// process table, health checks, restarts, logs and stats all work per replica.
+// An upgrade moves a unit to a fresh port; the service itself then also runs
+// on a lease. The warden holds these leases itself, under `{service}@{index}`.
+use crate::{
+    health::HealthCheck,
+    ports::{self, PortLease},
+    runtime::ServiceRuntime,
+    stats, stop_unit, update_runtime, ServiceConfig, WardenState, HEALTH_CHECK_FAILURES,
+    RESTART_TRACKERS, RUNNING_PROCESSES, WARDEN_STATE,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
+
+/// Between a service name and a replica index; never part of a service name
+const SEPARATOR: char = '@';
+
+lazy_static! {
+    /// Units of each service as last reserved, with the definition they were built from
+    static ref UNITS: Mutex<HashMap<String, (ServiceConfig, Vec<ServiceConfig>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The unit name of replica `index`
+pub fn unit_name(service: &str, index: u32) -> String {
+    if index == 0 {
+        service.to_string()
+    } else {
+        format!("{}{}{}", service, SEPARATOR, index)
+    }
+}
+
+/// The service a unit belongs to
+pub fn service_of(unit: &str) -> &str {
+    unit.split(SEPARATOR).next().unwrap_or(unit)
+}
+
+/// The replica index of a unit; 0 for the service itself
+pub fn index_of(unit: &str) -> u32 {
+    unit.split_once(SEPARATOR)
+        .and_then(|(_, index)| index.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Who holds the lease on a unit's port
+pub fn lease_owner(unit: &str) -> String {
+    ports::warden_owner(&format!("{}{}{}", service_of(unit), SEPARATOR, index_of(unit)))
+}
+
+/// The service and replica index whose port a lease holds, if it holds one
+fn unit_lease(owner: &str) -> Option<(&str, u32)> {
+    let (service, index) = ports::warden_owned(owner)?.split_once(SEPARATOR)?;
+    Some((service, index.parse().ok()?))
+}
+
+/// The port a unit runs on: its lease, or the configured port for a service that has none
+pub fn unit_port(service: &ServiceConfig, index: u32, leases: &[PortLease]) -> Option<u16> {
+    let owner = lease_owner(&unit_name(&service.name, index));
+    match leases.iter().find(|lease| lease.owner == owner) {
+        Some(lease) => Some(lease.port),
+        None => (index == 0).then_some(service.port),
+    }
+}
+
+/// The definition a unit runs with: the service's, under its own name and port
+///
+/// A TCP health check pinned to the service's port follows the unit to its
+/// port; one on any other port is left as configured.
+pub fn unit_config(service: &ServiceConfig, name: String, port: u16) -> ServiceConfig {
+    let healthcheck = match &service.healthcheck {
+        Some(HealthCheck::Tcp { port: Some(pinned) }) if *pinned == service.port => {
+            Some(HealthCheck::Tcp { port: Some(port) })
+        }
+        other => other.clone(),
+    };
+    ServiceConfig {
+        name,
+        port,
+        healthcheck,
+        ..service.clone()
+    }
+}
+
+/// Every unit of a service, the service itself first
+///
+/// The units reserved for the current definition; the monitor asks on every
+/// tick, so ports are only leased again once the definition changes or the
+/// units are dropped.
+pub fn units(service: &ServiceConfig) -> Vec<ServiceConfig> {
+    let cached = UNITS
+        .lock()
+        .unwrap()
+        .get(&service.name)
+        .filter(|(definition, _)| definition == service)
+        .map(|(_, units)| units.clone());
+    cached.unwrap_or_else(|| reserve(service))
+}
+
+/// Forget the cached units of a service, so the next ask reserves them again
+fn invalidate(service: &str) {
+    UNITS.lock().unwrap().remove(service);
+}
+
+/// Lease ports for a service's replicas and remember its units
+///
+/// Replicas get a port lease and a runtime entry here, when a service is
+/// started, enabled, registered or reloaded. A replica without a free port is
+/// marked failed and left out until the service is enabled again.
+pub fn reserve(service: &ServiceConfig) -> Vec<ServiceConfig> {
+    let leases = ports::leases();
+    let mut units = vec![unit_config(
+        service,
+        service.name.clone(),
+        unit_port(service, 0, &leases).unwrap_or(service.port),
+    )];
+    for index in 1..service.instances {
+        let name = unit_name(&service.name, index);
+        let lease = ports::reserve(&lease_owner(&name));
+
+        let mut state = WARDEN_STATE.lock().unwrap();
+        let runtime = state
+            .runtime
+            .entry(name.clone())
+            .or_insert_with(|| ServiceRuntime::new(true));
+        match lease {
+            Ok(lease) => units.push(unit_config(service, name, lease.port)),
+            Err(e) if !runtime.failed => {
+                eprintln!("❌ No port for {}: {}", name, e);
+                runtime.failed = true;
+                runtime.failure_reason = Some(format!("no port: {}", e));
+            }
+            Err(_) => {}
+        }
+    }
+    UNITS
+        .lock()
+        .unwrap()
+        .insert(service.name.clone(), (service.clone(), units.clone()));
+    units
+}
+
+/// Pick up a unit's new port after an upgrade moved it
+pub fn moved(unit: &str) {
+    invalidate(service_of(unit));
+}
+
+/// Replicas of a service with a live process, by index
+pub fn running_replicas(service: &str) -> Vec<String> {
+    let mut replicas: Vec<String> = RUNNING_PROCESSES
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|unit| service_of(unit) == service && index_of(unit) > 0)
+        .cloned()
+        .collect();
+    replicas.sort_by_key(|unit| index_of(unit));
+    replicas
+}
+
+fn replica_runtimes(state: &WardenState, service: &str) -> Vec<String> {
+    state
+        .runtime
+        .keys()
+        .filter(|unit| service_of(unit) == service && index_of(unit) > 0)
+        .cloned()
+        .collect()
+}
+
+/// Give failed replicas of a service another go, as enabling it does for the service
+pub fn reset(service: &str) {
+    invalidate(service);
+    let replicas = replica_runtimes(&WARDEN_STATE.lock().unwrap(), service);
+    for replica in replicas {
+        update_runtime(&replica, |runtime| {
+            runtime.failed = false;
+            runtime.failure_reason = None;
+        });
+        RESTART_TRACKERS.lock().unwrap().remove(&replica);
+        HEALTH_CHECK_FAILURES.lock().unwrap().remove(&replica);
+    }
+}
+
+/// Drop the status of a stopped service's replicas, for a definition that is replaced or removed
+///
+/// The service goes back to its configured port if an upgrade had moved it.
+pub fn forget(service: &str) {
+    invalidate(service);
+    let owner = lease_owner(service);
+    for lease in ports::leases().into_iter().filter(|lease| lease.owner == owner) {
+        let _ = ports::release(&owner, lease.port);
+    }
+    let replicas = {
+        let mut state = WARDEN_STATE.lock().unwrap();
+        let replicas = replica_runtimes(&state, service);
+        for replica in &replicas {
+            state.runtime.remove(replica);
+        }
+        replicas
+    };
+    for replica in replicas {
+        RESTART_TRACKERS.lock().unwrap().remove(&replica);
+        HEALTH_CHECK_FAILURES.lock().unwrap().remove(&replica);
+        stats::remove(&replica);
+    }
+}
+
+/// Stop replicas that are no longer wanted and give back their ports
+///
+/// A replica is unwanted once its service is removed or runs fewer instances;
+/// the lease of an upgraded service goes once the service is removed.
+pub fn prune() {
+    let unwanted: Vec<(String, PortLease)> = {
+        let leases = ports::leases();
+        let state = WARDEN_STATE.lock().unwrap();
+        leases
+            .into_iter()
+            .filter_map(|lease| {
+                let (service, index) = unit_lease(&lease.owner)?;
+                let unwanted = state.services.get(service).is_none_or(|s| index >= s.instances.max(1));
+                let unit = unit_name(service, index);
+                unwanted.then_some((unit, lease))
+            })
+            .collect()
+    };
+
+    for (unit, lease) in unwanted {
+        invalidate(service_of(&unit));
+        // Replica 0 is the service itself, stopped and forgotten by whatever removed it
+        if index_of(&unit) > 0 {
+            if RUNNING_PROCESSES.lock().unwrap().contains_key(&unit) {
+                if let Err(e) = stop_unit(&unit) {
+                    eprintln!("❌ Failed to stop {}: {}", unit, e);
+                }
+            }
+            WARDEN_STATE.lock().unwrap().runtime.remove(&unit);
+            RESTART_TRACKERS.lock().unwrap().remove(&unit);
+            HEALTH_CHECK_FAILURES.lock().unwrap().remove(&unit);
+            stats::remove(&unit);
+        }
+        let _ = ports::release(&lease.owner, lease.port);
+    }
+}
+
+/// Where a replica runs and how it is doing, as reported in `/services`
+#[derive(Debug, Serialize)]
+pub struct ReplicaView {
+    pub name: String,
+    pub index: u32,
+    /// `null` until the replica has been given a port
+    pub port: Option<u16>,
+    /// One of `healthy`, `degraded`, `starting`, `unhealthy`, `failed`, `stopped`, `disabled`
+    pub health: &'static str,
+    pub pid: Option<u32>,
+    /// As reported by the running process
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<String>,
+}
+
+/// Status of every replica of a service, the service itself first
+pub fn views(
+    state: &WardenState,
+    service: &ServiceConfig,
+    leases: &[PortLease],
+    pids: &HashMap<String, u32>,
+) -> Vec<ReplicaView> {
+    (0..service.instances.max(1))
+        .map(|index| {
+            let name = unit_name(&service.name, index);
+            let port = unit_port(service, index, leases);
+            let runtime = state.runtime.get(&name).cloned().unwrap_or_default();
+            // Replicas follow the service's enable/disable
+            let health = if state.is_enabled(&service.name) { runtime.health() } else { "disabled" };
+            ReplicaView {
+                index,
+                port,
+                health,
+                pid: pids.get(&name).copied(),
+                version: runtime.version,
+                failure_reason: runtime.failure_reason,
+                name,
+            }
+        })
+        .collect()
+}
+
+/// Units of a service that are up, with their ports, for the proxy
+///
+/// `None` when there is no such service.
+pub fn up_units(service: &str) -> Option<Vec<(String, u16)>> {
+    let leases = ports::leases();
+    let state = WARDEN_STATE.lock().unwrap();
+    let config = state.services.get(service)?;
+    if !state.is_enabled(service) {
+        return Some(Vec::new());
+    }
+    Some(
+        (0..config.instances.max(1))
+            .filter_map(|index| {
+                let name = unit_name(service, index);
+                let port = unit_port(config, index, &leases)?;
+                state.runtime.get(&name).filter(|r| r.is_up())?;
+                Some((name, port))
+            })
+            .collect(),
+    )
+}
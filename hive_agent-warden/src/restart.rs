@@ -0,0 +1,212 @@
+// Restart policies - exponential backoff with jitter and crash-loop detection
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Longest backoff exponent; keeps the shift from overflowing
+const MAX_BACKOFF_EXPONENT: u32 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Restart whenever the process exits or fails its health checks
+    #[default]
+    Always,
+    /// Restart only after a non-zero exit, a signal, or failed health checks
+    OnFailure,
+    /// Never restart; the service stays down until it is re-enabled
+    Never,
+}
+
+impl RestartPolicy {
+    /// Whether a service that stopped this way should be brought back
+    pub fn restarts_after(self, failure: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failure,
+            RestartPolicy::Never => false,
+        }
+    }
+}
+
+/// Limits that govern how often a service may be restarted
+#[derive(Debug, Clone, Copy)]
+pub struct RestartLimits {
+    /// Restarts allowed within `window` before the service is declared crash-looping
+    pub max_restarts: u32,
+    pub window: Duration,
+    pub backoff_initial: Duration,
+    pub backoff_max: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    /// Restart once the delay has passed
+    After(Duration),
+    /// Too many restarts within the window; stop trying
+    CrashLoop,
+}
+
+/// Restart bookkeeping for one service
+#[derive(Debug, Default)]
+pub struct RestartTracker {
+    /// Restarts within the crash-loop window, oldest first
+    recent: VecDeque<Instant>,
+    backoff_level: u32,
+    next_attempt: Option<Instant>,
+    /// Set when the restart policy says the service should stay down
+    held: bool,
+    /// Launches in a row that failed to spawn a process
+    failed_launches: u32,
+}
+
+impl RestartTracker {
+    /// Record a restart and decide when it may happen
+    pub fn schedule(&mut self, limits: &RestartLimits) -> RestartDecision {
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > limits.window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= limits.max_restarts as usize {
+            self.next_attempt = None;
+            self.held = true;
+            return RestartDecision::CrashLoop;
+        }
+
+        let delay = backoff_delay(limits, self.backoff_level);
+        self.recent.push_back(now);
+        self.backoff_level = (self.backoff_level + 1).min(MAX_BACKOFF_EXPONENT);
+        self.next_attempt = Some(now + delay);
+        RestartDecision::After(delay)
+    }
+
+    /// Restarts recorded within the current crash-loop window
+    pub fn recent_restarts(&self) -> usize {
+        self.recent.len()
+    }
+
+    /// Whether the monitor may (re)start the service now
+    pub fn is_due(&self) -> bool {
+        !self.held && self.next_attempt.is_none_or(|at| Instant::now() >= at)
+    }
+
+    /// Keep the service down until the tracker is reset
+    pub fn hold(&mut self) {
+        self.held = true;
+        self.next_attempt = None;
+    }
+
+    /// The service started; clear the pending attempt
+    pub fn started(&mut self) {
+        self.next_attempt = None;
+        self.failed_launches = 0;
+    }
+
+    /// Count a launch that failed to spawn; returns how many failed in a row
+    pub fn launch_failed(&mut self) -> u32 {
+        self.failed_launches += 1;
+        self.failed_launches
+    }
+
+    /// The service passed a health check; the next failure backs off from the start
+    pub fn recovered(&mut self) {
+        self.backoff_level = 0;
+    }
+}
+
+/// Exponential backoff with equal jitter: half the delay is fixed, half is random
+fn backoff_delay(limits: &RestartLimits, level: u32) -> Duration {
+    let base = limits
+        .backoff_initial
+        .saturating_mul(1 << level.min(MAX_BACKOFF_EXPONENT))
+        .min(limits.backoff_max);
+    let half = base / 2;
+    let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+    half + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RestartDecision, RestartLimits, RestartTracker};
+    use std::{thread, time::Duration};
+
+    fn limits(max_restarts: u32, window: Duration) -> RestartLimits {
+        RestartLimits {
+            max_restarts,
+            window,
+            backoff_initial: Duration::from_millis(100),
+            backoff_max: Duration::from_millis(1000),
+        }
+    }
+
+    fn delay(decision: RestartDecision) -> Duration {
+        match decision {
+            RestartDecision::After(delay) => delay,
+            RestartDecision::CrashLoop => panic!("unexpected crash loop"),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let limits = limits(100, Duration::from_secs(60));
+        let mut tracker = RestartTracker::default();
+        for base in [100, 200, 400, 800, 1000, 1000] {
+            let delay = delay(tracker.schedule(&limits)).as_millis() as u64;
+            assert!((base / 2..=base).contains(&delay), "{}ms outside {}ms backoff", delay, base);
+        }
+    }
+
+    #[test]
+    fn recovery_restarts_the_backoff() {
+        let limits = limits(100, Duration::from_secs(60));
+        let mut tracker = RestartTracker::default();
+        for _ in 0..4 {
+            tracker.schedule(&limits);
+        }
+        tracker.recovered();
+        assert!(delay(tracker.schedule(&limits)) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn a_scheduled_restart_waits_for_its_delay() {
+        let limits = limits(100, Duration::from_secs(60));
+        let mut tracker = RestartTracker::default();
+        assert!(tracker.is_due());
+        tracker.schedule(&limits);
+        assert!(!tracker.is_due());
+        tracker.started();
+        assert!(tracker.is_due());
+    }
+
+    #[test]
+    fn too_many_restarts_within_the_window_is_a_crash_loop() {
+        let limits = limits(3, Duration::from_secs(60));
+        let mut tracker = RestartTracker::default();
+        for _ in 0..3 {
+            delay(tracker.schedule(&limits));
+        }
+        assert_eq!(tracker.recent_restarts(), 3);
+        assert_eq!(tracker.schedule(&limits), RestartDecision::CrashLoop);
+        tracker.started();
+        assert!(!tracker.is_due(), "a crash-looping service stays down");
+    }
+
+    #[test]
+    fn restarts_outside_the_window_are_forgotten() {
+        let limits = limits(2, Duration::from_millis(20));
+        let mut tracker = RestartTracker::default();
+        delay(tracker.schedule(&limits));
+        delay(tracker.schedule(&limits));
+        thread::sleep(Duration::from_millis(50));
+        delay(tracker.schedule(&limits));
+        assert_eq!(tracker.recent_restarts(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+// Runtime state - live service status, kept apart from the declarative service definitions
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// What the warden currently knows about a service, as opposed to how it is configured
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceRuntime {
+    /// Whether the service should be running; starts from the definition and
+    /// follows enable/disable calls
+    pub enabled: bool,
+    pub running: bool,
+    /// Spawned but not yet healthy; cleared by the first passing health check
+    #[serde(default)]
+    pub starting: bool,
+    pub healthy: bool,
+    /// Answering health checks but reporting reduced functionality
+    #[serde(default)]
+    pub degraded: bool,
+    pub failed: bool,
+    /// Why the service was marked failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<String>,
+    /// What the running process reports on its `version_path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// When a starting service must be healthy by
+    #[serde(skip)]
+    pub boot_deadline: Option<Instant>,
+}
+
+impl ServiceRuntime {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// A process was just spawned and has `boot_timeout` to become healthy
+    pub fn mark_starting(&mut self, boot_timeout: Duration) {
+        self.running = true;
+        self.starting = true;
+        self.healthy = false;
+        self.degraded = false;
+        self.version = None;
+        self.boot_deadline = Some(Instant::now() + boot_timeout);
+    }
+
+    /// A health check passed
+    pub fn mark_healthy(&mut self) {
+        self.running = true;
+        self.starting = false;
+        self.healthy = true;
+        self.degraded = false;
+        self.boot_deadline = None;
+    }
+
+    /// A health check answered but reported degraded; this still ends the starting phase
+    pub fn mark_degraded(&mut self) {
+        self.running = true;
+        self.starting = false;
+        self.healthy = false;
+        self.degraded = true;
+        self.boot_deadline = None;
+    }
+
+    /// A health check failed
+    pub fn mark_unhealthy(&mut self) {
+        self.healthy = false;
+        self.degraded = false;
+    }
+
+    /// Running and answering health checks, possibly degraded
+    pub fn is_up(&self) -> bool {
+        self.running && (self.healthy || self.degraded)
+    }
+
+    /// The process is gone
+    pub fn mark_stopped(&mut self) {
+        self.running = false;
+        self.starting = false;
+        self.healthy = false;
+        self.degraded = false;
+        self.version = None;
+        self.boot_deadline = None;
+    }
+
+    /// Summarise the status as one word: `healthy`, `degraded`, `starting`,
+    /// `unhealthy`, `failed`, `stopped` or `disabled`
+    pub fn health(&self) -> &'static str {
+        if !self.enabled {
+            "disabled"
+        } else if self.failed {
+            "failed"
+        } else if self.healthy {
+            "healthy"
+        } else if self.degraded {
+            "degraded"
+        } else if self.starting {
+            "starting"
+        } else if self.running {
+            "unhealthy"
+        } else {
+            "stopped"
+        }
+    }
+
+    /// Whether a starting service has run out of time to become healthy
+    pub fn boot_timed_out(&self) -> bool {
+        self.starting && self.boot_deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Contents of the optional state file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub updated: String,
+    /// The profile last activated, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    pub services: BTreeMap<String, ServiceRuntime>,
+}
+
+/// Read a previously saved state file; a missing or unreadable file is treated as empty
+pub fn load_snapshot(path: &Path) -> Option<StateSnapshot> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            eprintln!("⚠️  Ignoring unreadable state file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Write the runtime state, replacing the previous file atomically
+pub fn save_snapshot(
+    path: &Path,
+    runtime: &HashMap<String, ServiceRuntime>,
+    active_profile: Option<&str>,
+) -> Result<()> {
+    let snapshot = StateSnapshot {
+        updated: chrono::Local::now().to_rfc3339(),
+        active_profile: active_profile.map(str::to_string),
+        services: runtime.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+    };
+
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&snapshot)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,588 @@
+// Sandboxing - namespaces, privilege drop and a seccomp allowlist applied to a service at spawn
+//
+// Every restriction is set up in the child between fork and exec. Anything that
+// cannot be applied fails the start: a service never runs with less isolation
+// than it asked for.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Optional per-service sandbox from `core_microservices.json`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sandbox {
+    /// Run as this user; needs `gid` as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Give the service an empty /tmp of its own
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub private_tmp: bool,
+    /// Mount everything read-only except `writable_paths`, /proc, /sys and /dev
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub read_only_root: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub writable_paths: Vec<String>,
+    /// Keep setuid binaries and file capabilities from granting privileges
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_new_privileges: bool,
+    /// Run in a network namespace of its own with only loopback
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub isolate_network: bool,
+    /// Seccomp allowlist file, relative to the config file's directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_profile: Option<String>,
+}
+
+impl Sandbox {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn needs_mount_namespace(&self) -> bool {
+        self.private_tmp || self.read_only_root
+    }
+}
+
+/// Contents of a seccomp profile file
+#[derive(Debug, Clone, Deserialize)]
+struct SeccompProfile {
+    /// What happens on a syscall that is not allowed
+    #[serde(default)]
+    default_action: SeccompAction,
+    allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SeccompAction {
+    /// Fail the syscall with EPERM
+    #[default]
+    Errno,
+    /// Kill the whole process
+    Kill,
+    /// Allow the syscall but log it to the kernel audit log; for building profiles
+    Log,
+}
+
+/// Set up a service's sandbox so it takes effect in the child before it execs
+///
+/// Must be called after any other `pre_exec` setup that needs the warden's privileges.
+pub fn apply(service_name: &str, sandbox: &Sandbox, command: &mut Command) -> Result<()> {
+    if sandbox.is_empty() {
+        return Ok(());
+    }
+    platform::apply(service_name, sandbox, command)
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{seccomp, Sandbox};
+    use crate::command::resolve_against_config;
+    use anyhow::{anyhow, Result};
+    use std::{
+        ffi::CString,
+        fs, io,
+        os::unix::{ffi::OsStrExt, process::CommandExt},
+        path::Path,
+        process::Command,
+    };
+
+    /// Mounts the kernel manages; they stay as they are under `read_only_root`
+    const KERNEL_MOUNTS: &[&str] = &["/proc", "/sys", "/dev"];
+
+    fn c_path(path: &Path) -> Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(|_| anyhow!("invalid path {}", path.display()))
+    }
+
+    fn is_under(path: &Path, parent: &Path) -> bool {
+        path.starts_with(parent)
+    }
+
+    /// Undo the octal escapes /proc/self/mountinfo uses for spaces and the like
+    fn unescape(field: &str) -> String {
+        let bytes = field.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+                u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok()
+            });
+            match (bytes[i], octal) {
+                (b'\\', Some(byte)) => {
+                    out.push(byte);
+                    i += 4;
+                }
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// Every mount to remount read-only, with the flags it must keep
+    fn read_only_mounts(sandbox: &Sandbox, writable: &[&Path]) -> Result<Vec<(CString, libc::c_ulong)>> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+        let mut mounts = Vec::new();
+        for line in mountinfo.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let (mount_point, options) = match (fields.get(4), fields.get(5)) {
+                (Some(mount_point), Some(options)) => (unescape(mount_point), *options),
+                _ => continue,
+            };
+            let path = Path::new(&mount_point);
+            let skip = KERNEL_MOUNTS.iter().any(|kernel| is_under(path, Path::new(kernel)))
+                || writable.iter().any(|w| is_under(path, w))
+                || (sandbox.private_tmp && is_under(path, Path::new("/tmp")));
+            if skip {
+                continue;
+            }
+            // A bind remount drops flags that aren't repeated
+            let mut flags = 0;
+            for option in options.split(',') {
+                flags |= match option {
+                    "nosuid" => libc::MS_NOSUID,
+                    "nodev" => libc::MS_NODEV,
+                    "noexec" => libc::MS_NOEXEC,
+                    _ => 0,
+                };
+            }
+            mounts.push((c_path(path)?, flags));
+        }
+        Ok(mounts)
+    }
+
+    /// Check what can be checked up front, so failures name their cause
+    fn validate(sandbox: &Sandbox) -> Result<()> {
+        if sandbox.uid.is_some() != sandbox.gid.is_some() {
+            return Err(anyhow!("sandbox uid and gid must be set together"));
+        }
+        // SAFETY: geteuid has no preconditions
+        let root = unsafe { libc::geteuid() } == 0;
+        let needs_root = sandbox.uid.is_some() || sandbox.needs_mount_namespace() || sandbox.isolate_network;
+        if needs_root && !root {
+            return Err(anyhow!("the requested sandbox needs the warden to run as root"));
+        }
+        if !sandbox.read_only_root && !sandbox.writable_paths.is_empty() {
+            return Err(anyhow!("writable_paths only apply with read_only_root"));
+        }
+        for path in &sandbox.writable_paths {
+            let path = Path::new(path);
+            if !path.is_absolute() || !path.exists() {
+                return Err(anyhow!("writable path {} must be an existing absolute path", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring up loopback in a fresh network namespace
+    ///
+    /// # Safety
+    /// Only makes raw syscalls; callable between fork and exec.
+    unsafe fn loopback_up() -> io::Result<()> {
+        /// `struct ifreq` with the flags member of its union
+        #[repr(C)]
+        struct IfreqFlags {
+            name: [u8; libc::IFNAMSIZ],
+            flags: libc::c_short,
+            _pad: [u8; 24],
+        }
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut request = IfreqFlags {
+            name: [0; libc::IFNAMSIZ],
+            flags: libc::IFF_UP as libc::c_short,
+            _pad: [0; 24],
+        };
+        request.name[..2].copy_from_slice(b"lo");
+        let result = libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &mut request);
+        libc::close(socket);
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn apply(_service_name: &str, sandbox: &Sandbox, command: &mut Command) -> Result<()> {
+        validate(sandbox)?;
+
+        let writable: Vec<&Path> = sandbox.writable_paths.iter().map(Path::new).collect();
+        let read_only = if sandbox.read_only_root {
+            read_only_mounts(sandbox, &writable)?
+        } else {
+            Vec::new()
+        };
+        let writable = writable.into_iter().map(c_path).collect::<Result<Vec<_>>>()?;
+        let filter = match &sandbox.seccomp_profile {
+            Some(profile) => Some(seccomp::load(&resolve_against_config(profile))?),
+            None => None,
+        };
+
+        let mut namespaces = 0;
+        if sandbox.needs_mount_namespace() {
+            namespaces |= libc::CLONE_NEWNS;
+        }
+        if sandbox.isolate_network {
+            namespaces |= libc::CLONE_NEWNET;
+        }
+        let ids = sandbox.uid.zip(sandbox.gid);
+        let private_tmp = sandbox.private_tmp;
+        let isolate_network = sandbox.isolate_network;
+        let no_new_privileges = sandbox.no_new_privileges || filter.is_some();
+        let root = c"/";
+        let tmp = c"/tmp";
+        let tmpfs = c"tmpfs";
+        let tmp_options = c"mode=1777";
+
+        // SAFETY: the closure runs between fork and exec and only makes raw
+        // syscalls on values prepared beforehand; it does not allocate
+        unsafe {
+            command.pre_exec(move || {
+                let check = |result: libc::c_int| {
+                    if result == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::last_os_error())
+                    }
+                };
+                if namespaces != 0 {
+                    check(libc::unshare(namespaces))?;
+                }
+                if namespaces & libc::CLONE_NEWNS != 0 {
+                    // Keep our mounts from propagating back to the host
+                    check(libc::mount(
+                        std::ptr::null(),
+                        root.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_REC | libc::MS_PRIVATE,
+                        std::ptr::null(),
+                    ))?;
+                }
+                // Writable paths become mounts of their own first, so the
+                // read-only remounts below don't reach them
+                for path in &writable {
+                    check(libc::mount(
+                        path.as_ptr(),
+                        path.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                }
+                for (mount_point, flags) in &read_only {
+                    check(libc::mount(
+                        std::ptr::null(),
+                        mount_point.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | flags,
+                        std::ptr::null(),
+                    ))?;
+                }
+                if private_tmp {
+                    check(libc::mount(
+                        tmpfs.as_ptr(),
+                        tmp.as_ptr(),
+                        tmpfs.as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        tmp_options.as_ptr().cast(),
+                    ))?;
+                }
+                if isolate_network {
+                    loopback_up()?;
+                }
+                if let Some((uid, gid)) = ids {
+                    check(libc::setgroups(0, std::ptr::null()))?;
+                    check(libc::setgid(gid))?;
+                    check(libc::setuid(uid))?;
+                }
+                if no_new_privileges {
+                    check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                }
+                if let Some(filter) = &filter {
+                    let program = libc::sock_fprog {
+                        len: filter.len() as libc::c_ushort,
+                        filter: filter.as_ptr() as *mut libc::sock_filter,
+                    };
+                    check(libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &program as *const libc::sock_fprog,
+                    ))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::Sandbox;
+    use anyhow::{anyhow, Result};
+    use std::process::Command;
+
+    /// Refuse to start rather than run without the requested isolation
+    pub fn apply(service_name: &str, _sandbox: &Sandbox, _command: &mut Command) -> Result<()> {
+        Err(anyhow!("{}: sandboxing is only supported on Linux", service_name))
+    }
+}
+
+/// Seccomp allowlists compiled to classic BPF
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use super::{SeccompAction, SeccompProfile};
+    use anyhow::{anyhow, Context, Result};
+    use std::{fs, path::Path};
+
+    /// Syscalls the spawn itself needs after the filter is installed
+    const ALWAYS_ALLOWED: &[&str] = &["execve", "write", "exit", "exit_group"];
+
+    // `struct seccomp_data` offsets
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    /// x32 syscalls share the x86_64 audit arch and are told apart by this bit
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// Syscalls a profile can name, on every architecture
+    macro_rules! syscalls {
+        ($($name:ident),* $(,)?) => {
+            &[$((stringify!($name), Some(libc::$name as u32))),*]
+        };
+    }
+
+    /// Legacy syscalls only x86_64 has; elsewhere they are accepted and skipped
+    macro_rules! legacy_syscalls {
+        ($($name:ident),* $(,)?) => {
+            &[$((
+                stringify!($name),
+                {
+                    #[cfg(target_arch = "x86_64")]
+                    let nr = Some(libc::$name as u32);
+                    #[cfg(not(target_arch = "x86_64"))]
+                    let nr = None;
+                    nr
+                },
+            )),*]
+        };
+    }
+
+    const SYSCALLS: &[(&str, Option<u32>)] = syscalls![
+        SYS_read, SYS_write, SYS_close, SYS_fstat, SYS_lseek, SYS_mmap, SYS_mprotect, SYS_munmap,
+        SYS_brk, SYS_rt_sigaction, SYS_rt_sigprocmask, SYS_rt_sigreturn, SYS_ioctl, SYS_pread64,
+        SYS_pwrite64, SYS_readv, SYS_writev, SYS_sched_yield, SYS_mremap, SYS_msync, SYS_mincore,
+        SYS_madvise, SYS_dup, SYS_dup3, SYS_nanosleep, SYS_getitimer, SYS_setitimer, SYS_getpid,
+        SYS_sendfile, SYS_socket, SYS_connect, SYS_accept, SYS_accept4, SYS_sendto, SYS_recvfrom,
+        SYS_sendmsg, SYS_recvmsg, SYS_shutdown, SYS_bind, SYS_listen, SYS_getsockname,
+        SYS_getpeername, SYS_socketpair, SYS_setsockopt, SYS_getsockopt, SYS_clone, SYS_clone3,
+        SYS_execve, SYS_execveat, SYS_exit, SYS_wait4, SYS_kill, SYS_uname, SYS_fcntl, SYS_flock,
+        SYS_fsync, SYS_fdatasync, SYS_truncate, SYS_ftruncate, SYS_getcwd, SYS_chdir, SYS_fchdir,
+        SYS_fchmod, SYS_fchown, SYS_umask, SYS_gettimeofday, SYS_getrlimit, SYS_getrusage,
+        SYS_sysinfo, SYS_times, SYS_getuid, SYS_getgid, SYS_setuid, SYS_setgid, SYS_geteuid,
+        SYS_getegid, SYS_setpgid, SYS_getppid, SYS_setsid, SYS_setreuid, SYS_setregid,
+        SYS_getgroups, SYS_setgroups, SYS_setresuid, SYS_getresuid, SYS_setresgid, SYS_getresgid,
+        SYS_getpgid, SYS_getsid, SYS_capget, SYS_capset, SYS_rt_sigpending, SYS_rt_sigtimedwait,
+        SYS_rt_sigqueueinfo, SYS_rt_sigsuspend, SYS_sigaltstack, SYS_personality, SYS_statfs,
+        SYS_fstatfs, SYS_getpriority, SYS_setpriority, SYS_sched_setparam, SYS_sched_getparam,
+        SYS_sched_setscheduler, SYS_sched_getscheduler, SYS_sched_get_priority_max,
+        SYS_sched_get_priority_min, SYS_mlock, SYS_munlock, SYS_mlockall, SYS_munlockall,
+        SYS_prctl, SYS_setrlimit, SYS_sync, SYS_gettid, SYS_readahead, SYS_setxattr,
+        SYS_getxattr, SYS_listxattr, SYS_removexattr, SYS_fgetxattr, SYS_tkill, SYS_futex,
+        SYS_sched_setaffinity, SYS_sched_getaffinity, SYS_io_setup, SYS_io_destroy,
+        SYS_io_getevents, SYS_io_submit, SYS_io_cancel, SYS_epoll_ctl, SYS_epoll_pwait,
+        SYS_set_tid_address, SYS_restart_syscall, SYS_fadvise64, SYS_timer_create,
+        SYS_timer_settime, SYS_timer_gettime, SYS_timer_getoverrun, SYS_timer_delete,
+        SYS_clock_gettime, SYS_clock_getres, SYS_clock_nanosleep, SYS_exit_group, SYS_tgkill,
+        SYS_waitid, SYS_inotify_add_watch, SYS_inotify_rm_watch, SYS_openat, SYS_mkdirat,
+        SYS_mknodat, SYS_fchownat, SYS_newfstatat, SYS_unlinkat, SYS_renameat, SYS_linkat,
+        SYS_symlinkat, SYS_readlinkat, SYS_fchmodat, SYS_faccessat, SYS_faccessat2, SYS_pselect6,
+        SYS_ppoll, SYS_unshare, SYS_set_robust_list, SYS_get_robust_list, SYS_splice, SYS_tee,
+        SYS_sync_file_range, SYS_vmsplice, SYS_utimensat, SYS_epoll_create1, SYS_eventfd2,
+        SYS_signalfd4, SYS_timerfd_create, SYS_timerfd_settime, SYS_timerfd_gettime,
+        SYS_fallocate, SYS_pipe2, SYS_inotify_init1, SYS_preadv, SYS_pwritev, SYS_recvmmsg,
+        SYS_sendmmsg, SYS_prlimit64, SYS_getcpu, SYS_getrandom, SYS_memfd_create, SYS_statx,
+        SYS_renameat2, SYS_copy_file_range, SYS_rseq, SYS_membarrier, SYS_mlock2, SYS_pidfd_open,
+        SYS_close_range, SYS_getdents64, SYS_mount, SYS_umount2, SYS_chroot, SYS_ptrace,
+        SYS_reboot, SYS_init_module, SYS_delete_module, SYS_bpf, SYS_perf_event_open,
+    ];
+
+    const LEGACY_SYSCALLS: &[(&str, Option<u32>)] = legacy_syscalls![
+        SYS_open, SYS_stat, SYS_lstat, SYS_poll, SYS_access, SYS_pipe, SYS_select, SYS_dup2,
+        SYS_fork, SYS_vfork, SYS_getdents, SYS_readlink, SYS_unlink, SYS_mkdir, SYS_rmdir,
+        SYS_rename, SYS_chmod, SYS_chown, SYS_lchown, SYS_creat, SYS_link, SYS_symlink, SYS_time,
+        SYS_epoll_wait, SYS_epoll_create, SYS_eventfd, SYS_signalfd, SYS_inotify_init,
+        SYS_arch_prctl, SYS_getpgrp, SYS_alarm, SYS_pause, SYS_utimes, SYS_mknod,
+    ];
+
+    /// The syscall number for a name, `None` when it doesn't exist on this architecture
+    fn number(name: &str) -> Result<Option<u32>> {
+        let symbol = format!("SYS_{}", name);
+        SYSCALLS
+            .iter()
+            .chain(LEGACY_SYSCALLS)
+            .find(|(known, _)| *known == symbol)
+            .map(|(_, nr)| *nr)
+            .ok_or_else(|| anyhow!("unknown syscall {:?} in seccomp profile", name))
+    }
+
+    fn statement(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// Read a profile and compile it; unknown syscall names are an error
+    pub fn load(path: &Path) -> Result<Vec<libc::sock_filter>> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("cannot read seccomp profile {}", path.display()))?;
+        let profile: SeccompProfile = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid seccomp profile {}", path.display()))?;
+        compile(&profile)
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn compile(profile: &SeccompProfile) -> Result<Vec<libc::sock_filter>> {
+        let mut allowed = Vec::new();
+        for name in profile.allow.iter().map(String::as_str).chain(ALWAYS_ALLOWED.iter().copied()) {
+            if let Some(nr) = number(name)? {
+                if !allowed.contains(&nr) {
+                    allowed.push(nr);
+                }
+            }
+        }
+        let default = match profile.default_action {
+            SeccompAction::Errno => libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            SeccompAction::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+            SeccompAction::Log => libc::SECCOMP_RET_LOG,
+        };
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let equals = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+        let ret = libc::BPF_RET | libc::BPF_K;
+
+        // Anything from another architecture is killed outright
+        let mut filter = vec![
+            statement(load, DATA_ARCH),
+            jump(equals, AUDIT_ARCH, 1, 0),
+            statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, DATA_NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, X32_SYSCALL_BIT, 0, 1),
+            statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+        for nr in allowed {
+            filter.push(jump(equals, nr, 0, 1));
+            filter.push(statement(ret, libc::SECCOMP_RET_ALLOW));
+        }
+        filter.push(statement(ret, default));
+        Ok(filter)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn compile(_profile: &SeccompProfile) -> Result<Vec<libc::sock_filter>> {
+        Err(anyhow!("seccomp profiles are only supported on x86_64 and aarch64"))
+    }
+
+    #[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mod tests {
+        use super::{compile, load, AUDIT_ARCH};
+        use crate::sandbox::SeccompProfile;
+        use std::path::Path;
+
+        fn profile(json: &str) -> SeccompProfile {
+            serde_json::from_str(json).unwrap()
+        }
+
+        /// Run a filter the way the kernel would, for one syscall
+        fn run(filter: &[libc::sock_filter], arch: u32, nr: u32) -> u32 {
+            let mut pc = 0;
+            let mut acc = 0;
+            loop {
+                let insn = filter[pc];
+                let code = insn.code as u32;
+                pc += 1;
+                match code {
+                    _ if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS => {
+                        acc = if insn.k == 0 { nr } else { arch };
+                    }
+                    _ if code == libc::BPF_RET | libc::BPF_K => return insn.k,
+                    _ => {
+                        let taken = if code & 0xf0 == libc::BPF_JEQ { acc == insn.k } else { acc >= insn.k };
+                        pc += if taken { insn.jt } else { insn.jf } as usize;
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn allowed_syscalls_pass_and_the_rest_get_the_default_action() {
+            let filter = compile(&profile(r#"{"allow": ["read", "openat"]}"#)).unwrap();
+            let errno = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+            assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_read as u32), libc::SECCOMP_RET_ALLOW);
+            assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_openat as u32), libc::SECCOMP_RET_ALLOW);
+            assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_execve as u32), libc::SECCOMP_RET_ALLOW);
+            assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_mount as u32), errno);
+
+            let filter = compile(&profile(r#"{"default_action": "kill", "allow": []}"#)).unwrap();
+            assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_read as u32), libc::SECCOMP_RET_KILL_PROCESS);
+        }
+
+        #[test]
+        fn other_architectures_are_killed() {
+            let filter = compile(&profile(r#"{"allow": ["read"]}"#)).unwrap();
+            assert_eq!(run(&filter, 0x4000_0003, libc::SYS_read as u32), libc::SECCOMP_RET_KILL_PROCESS);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        #[test]
+        fn x32_syscalls_are_killed() {
+            let filter = compile(&profile(r#"{"allow": ["read"]}"#)).unwrap();
+            let x32_read = super::X32_SYSCALL_BIT | libc::SYS_read as u32;
+            assert_eq!(run(&filter, AUDIT_ARCH, x32_read), libc::SECCOMP_RET_KILL_PROCESS);
+        }
+
+        #[test]
+        fn duplicates_are_compiled_once() {
+            let once = compile(&profile(r#"{"allow": ["read"]}"#)).unwrap();
+            let twice = compile(&profile(r#"{"allow": ["read", "read", "write"]}"#)).unwrap();
+            assert_eq!(once.len(), twice.len());
+        }
+
+        #[test]
+        fn unknown_syscalls_are_rejected() {
+            let error = compile(&profile(r#"{"allow": ["read", "teleport"]}"#)).unwrap_err();
+            assert!(error.to_string().contains("teleport"), "{}", error);
+        }
+
+        #[test]
+        fn the_shipped_profile_compiles() {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("deps/seccomp/service.json");
+            assert!(load(&path).is_ok());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// Warden settings - warden-level options kept apart from the service definitions
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf};
+
+pub const SETTINGS_FILE_NAME: &str = "warden_settings.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WardenSettings {
+    /// Where runtime state is saved, relative to the settings file; `null` keeps it in memory only
+    pub state_file: Option<PathBuf>,
+    /// Pid records and output FIFOs that let a restarted warden reattach to running
+    /// services, relative to the settings file; `null` (the default) restarts services
+    /// with the warden. A service blocks writing its output while the warden is down
+    /// and the FIFO is full.
+    pub runtime_dir: Option<PathBuf>,
+    /// Reload `core_microservices.json` automatically when it changes on disk
+    pub watch_config: bool,
+    pub logs: LogSettings,
+    pub ports: PortSettings,
+    pub history: HistorySettings,
+    pub api: ApiSettings,
+    pub limits: LimitSettings,
+    /// Named sets of services, e.g. `"vision": ["hive_agent-camera-server", "hive_agent-director"]`,
+    /// activated through `/profile/{name}/activate`
+    pub profiles: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for WardenSettings {
+    fn default() -> Self {
+        Self {
+            state_file: Some(PathBuf::from("warden_state.json")),
+            runtime_dir: None,
+            watch_config: false,
+            logs: LogSettings::default(),
+            ports: PortSettings::default(),
+            history: HistorySettings::default(),
+            api: ApiSettings::default(),
+            limits: LimitSettings::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Directory for per-service log files, relative to the settings file
+    pub directory: PathBuf,
+    /// Rotate the active file once it reaches this size
+    pub max_file_bytes: u64,
+    /// Rotate the active file once it is this old
+    pub max_file_age_secs: u64,
+    /// Gzip rotated segments
+    pub compress: bool,
+    /// Archived segments kept per service
+    pub max_archives: usize,
+    /// Archived segments older than this are deleted
+    pub max_archive_age_days: u64,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("logs"),
+            max_file_bytes: 10 * 1024 * 1024,
+            max_file_age_secs: 24 * 60 * 60,
+            compress: true,
+            max_archives: 10,
+            max_archive_age_days: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortSettings {
+    /// First port handed out when a preferred port is unavailable
+    pub range_start: u16,
+    /// Last port handed out, inclusive
+    pub range_end: u16,
+    /// Where port leases are saved, relative to the settings file; `null` keeps them in memory only
+    pub leases_file: Option<PathBuf>,
+    /// Lifetime of leases that don't ask for one; `null` means they last until released
+    pub default_lease_ttl_secs: Option<u64>,
+}
+
+impl Default for PortSettings {
+    fn default() -> Self {
+        Self {
+            range_start: 6000,
+            range_end: 7000,
+            leases_file: Some(PathBuf::from("port_leases.json")),
+            default_lease_ttl_secs: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Append-only event history, relative to the settings file; `null` keeps only recent events in memory
+    pub file: Option<PathBuf>,
+    /// Rotate the history file once it reaches this size
+    pub max_file_bytes: u64,
+    /// Rotated history files kept; older events are dropped with the oldest file
+    pub max_archives: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            file: Some(PathBuf::from("history.jsonl")),
+            max_file_bytes: 10 * 1024 * 1024,
+            max_archives: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiSettings {
+    /// Listen on 127.0.0.1 instead of every interface; without API tokens the warden stays on 127.0.0.1 regardless
+    pub localhost_only: bool,
+    /// Bearer tokens, relative to the settings file; without tokens the API is open
+    pub tokens_file: Option<PathBuf>,
+    /// Require a read token for GET endpoints too, not only for changes
+    pub protect_reads: bool,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            localhost_only: true,
+            tokens_file: Some(PathBuf::from("api_tokens.json")),
+            protect_reads: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitSettings {
+    /// Enforce memory and CPU limits through cgroups v2 when the system supports it
+    pub cgroups: bool,
+    /// Cgroup to create service cgroups under; `null` uses the warden's own cgroup
+    pub cgroup_root: Option<PathBuf>,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        Self {
+            cgroups: true,
+            cgroup_root: None,
+        }
+    }
+}
+
+/// Load warden settings, falling back to defaults when the file is missing or invalid
+///
+/// Relative paths are resolved against the settings file's directory.
+pub fn load_settings(path: &Path) -> WardenSettings {
+    let mut settings = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("⚠️  Invalid settings in {}: {}", path.display(), e);
+            eprintln!("   Using default settings...");
+            WardenSettings::default()
+        }),
+        Err(_) => WardenSettings::default(),
+    };
+
+    if let Some(base) = path.parent() {
+        if settings.logs.directory.is_relative() {
+            settings.logs.directory = base.join(&settings.logs.directory);
+        }
+        if let Some(state_file) = settings.state_file.as_mut().filter(|p| p.is_relative()) {
+            *state_file = base.join(&*state_file);
+        }
+        if let Some(runtime_dir) = settings.runtime_dir.as_mut().filter(|p| p.is_relative()) {
+            *runtime_dir = base.join(&*runtime_dir);
+        }
+        if let Some(leases_file) = settings.ports.leases_file.as_mut().filter(|p| p.is_relative()) {
+            *leases_file = base.join(&*leases_file);
+        }
+        if let Some(history_file) = settings.history.file.as_mut().filter(|p| p.is_relative()) {
+            *history_file = base.join(&*history_file);
+        }
+        if let Some(tokens_file) = settings.api.tokens_file.as_mut().filter(|p| p.is_relative()) {
+            *tokens_file = base.join(&*tokens_file);
+        }
+    }
+
+    if settings.ports.range_start > settings.ports.range_end {
+        eprintln!(
+            "⚠️  Invalid port range {}-{} in {}; using the default range",
+            settings.ports.range_start,
+            settings.ports.range_end,
+            path.display()
+        );
+        let defaults = PortSettings::default();
+        settings.ports.range_start = defaults.range_start;
+        settings.ports.range_end = defaults.range_end;
+    }
+
+    settings
+}
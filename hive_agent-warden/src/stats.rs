@@ -0,0 +1,206 @@
+// Resource usage sampling - CPU, memory, threads and descriptors of each service from /proc
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Time between samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Samples kept per service; ten minutes at the default interval
+const HISTORY_LEN: usize = 120;
+
+lazy_static! {
+    static ref STATS: Mutex<HashMap<String, ServiceStats>> = Mutex::new(HashMap::new());
+}
+
+/// Usage of a service's process group at one point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub timestamp: String,
+    pub pid: u32,
+    /// Share of one core since the previous sample; above 100 on several cores
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub open_fds: u64,
+    /// Processes in the service's process group, the main process included
+    pub processes: u64,
+    pub uptime_secs: u64,
+}
+
+#[derive(Default)]
+struct ServiceStats {
+    /// CPU ticks of the process group at the last sample, to turn into a rate
+    last_cpu: Option<(u32, u64, Instant)>,
+    samples: VecDeque<Sample>,
+}
+
+/// Sample every running service; `pids` maps service names to their main process
+pub fn sample_all(pids: &HashMap<String, u32>) {
+    let readings: Vec<(&String, u32, proc::Reading)> = pids
+        .iter()
+        .filter_map(|(name, &pid)| Some((name, pid, proc::read_group(pid)?)))
+        .collect();
+    let now = Instant::now();
+    let timestamp = chrono::Local::now().to_rfc3339();
+
+    let mut stats = STATS.lock().unwrap();
+    for (name, pid, reading) in readings {
+        let entry = stats.entry(name.clone()).or_default();
+        let cpu_percent = match entry.last_cpu {
+            // A new process starts a new baseline
+            Some((last_pid, last_ticks, at)) if last_pid == pid => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                let used = reading.cpu_ticks.saturating_sub(last_ticks) as f64 / proc::ticks_per_sec();
+                if elapsed > 0.0 { used / elapsed * 100.0 } else { 0.0 }
+            }
+            _ => 0.0,
+        };
+        entry.last_cpu = Some((pid, reading.cpu_ticks, now));
+
+        if entry.samples.len() == HISTORY_LEN {
+            entry.samples.pop_front();
+        }
+        entry.samples.push_back(Sample {
+            timestamp: timestamp.clone(),
+            pid,
+            cpu_percent: (cpu_percent * 10.0).round() / 10.0,
+            rss_bytes: reading.rss_bytes,
+            threads: reading.threads,
+            open_fds: reading.open_fds,
+            processes: reading.processes,
+            uptime_secs: reading.uptime_secs,
+        });
+    }
+}
+
+/// Recorded samples for a service, oldest first
+pub fn history(name: &str) -> Vec<Sample> {
+    STATS
+        .lock()
+        .unwrap()
+        .get(name)
+        .map(|stats| stats.samples.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// The most recent sample of every service
+pub fn latest() -> HashMap<String, Sample> {
+    STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(name, stats)| Some((name.clone(), stats.samples.back()?.clone())))
+        .collect()
+}
+
+/// Drop a removed service's samples
+pub fn remove(name: &str) {
+    STATS.lock().unwrap().remove(name);
+}
+
+#[cfg(target_os = "linux")]
+mod proc {
+    use std::fs;
+
+    /// Totals over a process group
+    #[derive(Default)]
+    pub struct Reading {
+        pub cpu_ticks: u64,
+        pub rss_bytes: u64,
+        pub threads: u64,
+        pub open_fds: u64,
+        pub processes: u64,
+        pub uptime_secs: u64,
+    }
+
+    /// The fields of /proc/{pid}/stat after the command name
+    struct Stat {
+        pgrp: u32,
+        cpu_ticks: u64,
+        threads: u64,
+        start_ticks: u64,
+        rss_pages: u64,
+    }
+
+    fn stat(pid: u32) -> Option<Stat> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        // Indexes are stat(5) field numbers minus 3
+        let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+        Some(Stat {
+            pgrp: field(5)? as u32,
+            cpu_ticks: field(14)? + field(15)?,
+            threads: field(20)?,
+            start_ticks: field(22)?,
+            rss_pages: field(24)?,
+        })
+    }
+
+    pub fn ticks_per_sec() -> f64 {
+        // SAFETY: sysconf has no preconditions
+        unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64
+    }
+
+    fn page_size() -> u64 {
+        // SAFETY: sysconf has no preconditions
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64
+    }
+
+    fn open_fds(pid: u32) -> u64 {
+        fs::read_dir(format!("/proc/{}/fd", pid)).map_or(0, |entries| entries.count() as u64)
+    }
+
+    fn system_uptime_secs() -> Option<f64> {
+        fs::read_to_string("/proc/uptime").ok()?.split_whitespace().next()?.parse().ok()
+    }
+
+    /// Add up every process in the group led by `pid`, which services are spawned as
+    pub fn read_group(pid: u32) -> Option<Reading> {
+        let leader = stat(pid)?;
+        let uptime = system_uptime_secs()? - leader.start_ticks as f64 / ticks_per_sec();
+        let mut reading = Reading {
+            uptime_secs: uptime.max(0.0) as u64,
+            ..Reading::default()
+        };
+
+        let members = fs::read_dir("/proc")
+            .ok()?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter_map(|member| Some((member, stat(member)?)))
+            .filter(|(member, stat)| *member == pid || stat.pgrp == pid);
+        for (member, stat) in members {
+            reading.cpu_ticks += stat.cpu_ticks;
+            reading.rss_bytes += stat.rss_pages * page_size();
+            reading.threads += stat.threads;
+            reading.open_fds += open_fds(member);
+            reading.processes += 1;
+        }
+        Some(reading)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod proc {
+    /// Totals over a process group
+    pub struct Reading {
+        pub cpu_ticks: u64,
+        pub rss_bytes: u64,
+        pub threads: u64,
+        pub open_fds: u64,
+        pub processes: u64,
+        pub uptime_secs: u64,
+    }
+
+    pub fn ticks_per_sec() -> f64 {
+        1.0
+    }
+
+    /// No /proc to read
+    pub fn read_group(_pid: u32) -> Option<Reading> {
+        None
+    }
+}
@@ -0,0 +1,368 @@
+// Upgrades - start a rebuilt service beside the running one and switch over once it is healthy
+//
+// Every unit of the service gets a second process on a fresh port. Only when
+// all of them pass a health check do discovery and the proxy move to the new
+// ports and the old processes get stopped; otherwise the new processes are
+// stopped and the old ones carry on as if nothing happened.
+use crate::{
+    events::{self, EventKind},
+    health::{self, HealthStatus},
+    limits,
+    logs::{self, LogStream},
+    metrics,
+    process::{self, ServiceProcess},
+    reload::CONFIG_LOCK,
+    ports, replicas, start_service, update_runtime, ServiceConfig, HEALTH_CHECK_FAILURES,
+    RUNNING_PROCESSES, SHUTTING_DOWN, WARDEN_STATE,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{atomic::Ordering, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often new processes are probed while they boot
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// Lines searched for the last thing a new process printed before it exited
+const EXIT_OUTPUT_LINES: usize = 50;
+/// New ports are leased for the boot timeout plus this, so they lapse if the warden dies mid-upgrade
+const STAGING_LEASE_MARGIN_SECS: u64 = 60;
+
+lazy_static! {
+    /// Held by the monitor for a whole tick, so no unit changes process or port in the middle of one
+    pub static ref SWITCH_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Why an upgrade did not happen
+#[derive(Debug)]
+pub enum UpgradeError {
+    NotFound(String),
+    /// The service isn't in a state an upgrade can start from
+    Conflict(String),
+    /// The new processes didn't come up; the old ones kept running
+    RolledBack(String),
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeError::NotFound(msg) | UpgradeError::Conflict(msg) | UpgradeError::RolledBack(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+/// One unit moved to a new process
+#[derive(Debug, Serialize)]
+pub struct UnitUpgrade {
+    pub name: String,
+    pub from_port: u16,
+    pub to_port: u16,
+    pub pid: u32,
+    /// As reported by the new process, when the service has a `version_path`
+    pub version: Option<String>,
+    /// As reported by the process it replaced
+    pub previous_version: Option<String>,
+}
+
+/// A new process started beside a unit's running one
+struct Staged {
+    /// The unit on its new port
+    unit: ServiceConfig,
+    old_port: u16,
+    old_pid: u32,
+    process: ServiceProcess,
+    /// Holds the new port until the switch
+    lease_owner: String,
+    /// The first passing health check
+    status: Option<HealthStatus>,
+    version: Option<String>,
+    /// The unit's last log line before the new process started; both processes log to the unit
+    output_after: Option<u64>,
+}
+
+fn staging_owner(unit: &str) -> String {
+    ports::warden_owner(&format!("{}:upgrade", unit))
+}
+
+/// Start a second process for a unit on a fresh port
+fn stage(unit: &ServiceConfig, old_pid: u32) -> Result<Staged, String> {
+    let lease_owner = staging_owner(&unit.name);
+    let ttl_secs = unit.boot_timeout_millisecs / 1000 + STAGING_LEASE_MARGIN_SECS;
+    let lease = ports::allocate(&lease_owner, None, Some(ttl_secs))
+        .map_err(|e| format!("no port for a new {}: {}", unit.name, e))?
+        .lease;
+    let new_unit = replicas::unit_config(unit, unit.name.clone(), lease.port);
+
+    // The running process keeps its output FIFOs; the new one gets fresh ones
+    #[cfg(unix)]
+    process::fifo::set_aside(&unit.name);
+    let output_after = logs::tail(&unit.name, 1).last().map(|line| line.seq);
+    // Its own cgroup, so the two processes don't share the unit's memory and CPU caps
+    match start_service(&new_unit, &limits::staging_cgroup(&unit.name, old_pid)) {
+        Ok(child) => Ok(Staged {
+            unit: new_unit,
+            old_port: unit.port,
+            old_pid,
+            process: ServiceProcess::Child(child),
+            lease_owner,
+            status: None,
+            version: None,
+            output_after,
+        }),
+        Err(e) => {
+            limits::release(&unit.name);
+            #[cfg(unix)]
+            process::fifo::restore(&unit.name);
+            let _ = ports::release(&lease_owner, lease.port);
+            Err(format!("new {} failed to start: {}", unit.name, e))
+        }
+    }
+}
+
+/// What a new process last wrote to stderr, such as why it couldn't bind its port
+fn last_error_line(new: &Staged) -> Option<String> {
+    // Its final lines may still be on their way from the pipe
+    thread::sleep(PROBE_INTERVAL);
+    logs::tail(&new.unit.name, EXIT_OUTPUT_LINES)
+        .into_iter()
+        .rev()
+        .filter(|line| new.output_after.is_none_or(|seq| line.seq > seq))
+        .find(|line| matches!(line.stream, LogStream::Stderr) && !line.line.trim().is_empty())
+        .map(|line| line.line)
+}
+
+/// Probe the new processes until all are up, each within its boot timeout
+fn wait_until_up(rt: &tokio::runtime::Runtime, staged: &mut [Staged]) -> Result<(), String> {
+    let started = Instant::now();
+    loop {
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            return Err("the warden is shutting down".to_string());
+        }
+        let mut waiting = false;
+        for new in staged.iter_mut().filter(|new| new.status.is_none()) {
+            // One that can't bind the port it was given exits here rather than timing out
+            if let Ok(Some(exit)) = new.process.try_wait() {
+                let output = last_error_line(new).map(|line| format!(": {}", line)).unwrap_or_default();
+                return Err(format!(
+                    "new {} {} on port {}{}",
+                    new.unit.name,
+                    exit.describe(),
+                    new.unit.port,
+                    output
+                ));
+            }
+            let status = rt.block_on(health::probe(&new.unit));
+            if status.is_up() {
+                println!("💚 New {} on port {} is up", new.unit.name, new.unit.port);
+                new.status = Some(status);
+                new.version = rt.block_on(health::version(&new.unit));
+            } else if started.elapsed() >= Duration::from_millis(new.unit.boot_timeout_millisecs) {
+                return Err(format!(
+                    "new {} not healthy within boot timeout of {}ms",
+                    new.unit.name, new.unit.boot_timeout_millisecs
+                ));
+            } else {
+                waiting = true;
+            }
+        }
+        if !waiting {
+            return Ok(());
+        }
+        thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+/// Stop the new processes and hand the old ones their output back
+fn roll_back(service: &str, staged: Vec<Staged>, reason: String) -> UpgradeError {
+    eprintln!("↩️  Upgrade of {} rolled back: {}", service, reason);
+    let grace = staged.first().map(|new| new.unit.stop_timeout_millisecs).unwrap_or_default();
+    for mut new in staged {
+        if let Err(e) = process::terminate(&new.unit.name, &mut new.process, Duration::from_millis(grace)) {
+            eprintln!("❌ Failed to stop new {}: {}", new.unit.name, e);
+        }
+        // The old process keeps the cgroup it is in
+        limits::release(&new.unit.name);
+        #[cfg(unix)]
+        {
+            let old_running = RUNNING_PROCESSES.lock().unwrap()
+                .get(&new.unit.name)
+                .is_some_and(|process| process.id() == new.old_pid);
+            // A process the monitor started meanwhile has FIFOs of its own
+            if old_running {
+                process::fifo::restore(&new.unit.name);
+            } else {
+                process::fifo::discard(&new.unit.name);
+            }
+        }
+        let _ = ports::release(&new.lease_owner, new.unit.port);
+    }
+    events::emit(Some(service), EventKind::UpgradeRolledBack { reason: reason.clone() });
+    UpgradeError::RolledBack(format!("Upgrade of {} rolled back: {}", service, reason))
+}
+
+/// Put a new process in place of a unit's old one and move the unit to its port
+///
+/// Returns the old process, or gives the new one back if the old one was
+/// stopped or replaced in the meantime.
+fn switch(new: Staged) -> Result<(UnitUpgrade, ServiceProcess), Box<Staged>> {
+    let name = new.unit.name.clone();
+    let pid = new.process.id();
+    let old = {
+        let mut processes = RUNNING_PROCESSES.lock().unwrap();
+        if processes.get(&name).is_none_or(|process| process.id() != new.old_pid) {
+            return Err(Box::new(new));
+        }
+        processes.insert(name.clone(), new.process).expect("old process checked above")
+    };
+
+    // Discovery, the proxy and the monitor follow the lease
+    let owner = replicas::lease_owner(&name);
+    if ports::lease(new.old_port).is_some_and(|lease| lease.owner == owner) {
+        let _ = ports::release(&owner, new.old_port);
+    }
+    if let Err(e) = ports::transfer(&new.lease_owner, new.unit.port, &owner) {
+        eprintln!("⚠️  Failed to keep port {} for {}: {}", new.unit.port, name, e);
+    }
+    replicas::moved(&name);
+    process::record(&name, pid, new.unit.port);
+    metrics::service_started(&name);
+    HEALTH_CHECK_FAILURES.lock().unwrap().remove(&name);
+
+    let status = new.status;
+    let version = new.version.clone();
+    let previous_version = update_runtime(&name, |runtime| {
+        match status {
+            Some(HealthStatus::Degraded) => runtime.mark_degraded(),
+            _ => runtime.mark_healthy(),
+        }
+        std::mem::replace(&mut runtime.version, version)
+    })
+    .flatten();
+
+    events::emit(Some(&name), EventKind::Started { pid });
+    events::emit(
+        Some(&name),
+        EventKind::Upgraded {
+            from_port: new.old_port,
+            to_port: new.unit.port,
+            pid,
+            version: new.version.clone(),
+        },
+    );
+    println!("🔀 {} switched from port {} to {} (pid {})", name, new.old_port, new.unit.port, pid);
+    Ok((
+        UnitUpgrade {
+            name,
+            from_port: new.old_port,
+            to_port: new.unit.port,
+            pid,
+            version: new.version,
+            previous_version,
+        },
+        old,
+    ))
+}
+
+/// Replace every process of a service with a fresh one, without a moment where none is up
+///
+/// Blocks until the new processes are healthy and switched in, or until they
+/// are rolled back after the service's boot timeout.
+pub fn upgrade(name: &str) -> Result<Vec<UnitUpgrade>, UpgradeError> {
+    // Service definitions can't change underneath an upgrade
+    let _guard = CONFIG_LOCK.lock().unwrap();
+
+    let service = {
+        let state = WARDEN_STATE.lock().unwrap();
+        let service = state
+            .services
+            .get(name)
+            .cloned()
+            .ok_or_else(|| UpgradeError::NotFound(format!("Service {} not found", name)))?;
+        if service.name == "hive_agent-warden" {
+            return Err(UpgradeError::Conflict("The warden can't upgrade itself".to_string()));
+        }
+        if !state.is_enabled(name) {
+            return Err(UpgradeError::Conflict(format!("{} is disabled", name)));
+        }
+        service
+    };
+
+    let units = replicas::units(&service);
+    let pids: HashMap<String, u32> = RUNNING_PROCESSES.lock().unwrap()
+        .iter()
+        .map(|(name, process)| (name.clone(), process.id()))
+        .collect();
+    {
+        let state = WARDEN_STATE.lock().unwrap();
+        for unit in &units {
+            let runtime = state.runtime.get(&unit.name).cloned().unwrap_or_default();
+            if !pids.contains_key(&unit.name) || !runtime.is_up() {
+                return Err(UpgradeError::Conflict(format!(
+                    "{} is {}; an upgrade starts from a running, healthy service",
+                    unit.name,
+                    runtime.health()
+                )));
+            }
+        }
+    }
+
+    println!("⬆️  Upgrading {}", name);
+    let mut staged = Vec::new();
+    for unit in &units {
+        match stage(unit, pids[&unit.name]) {
+            Ok(new) => staged.push(new),
+            Err(reason) => return Err(roll_back(name, staged, reason)),
+        }
+    }
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    if let Err(reason) = wait_until_up(&rt, &mut staged) {
+        return Err(roll_back(name, staged, reason));
+    }
+
+    let (switched, retired, lost) = {
+        let switch_guard = SWITCH_LOCK.lock().unwrap();
+        if !WARDEN_STATE.lock().unwrap().is_enabled(name) {
+            drop(switch_guard);
+            return Err(roll_back(name, staged, format!("{} was disabled meanwhile", name)));
+        }
+        let mut switched = Vec::new();
+        let mut retired = Vec::new();
+        let mut lost = Vec::new();
+        for new in staged {
+            match switch(new) {
+                Ok((upgraded, old)) => {
+                    retired.push((upgraded.name.clone(), old));
+                    switched.push(upgraded);
+                }
+                Err(new) => lost.push(*new),
+            }
+        }
+        (switched, retired, lost)
+    };
+
+    // Stopped or restarted while the new process booted; the newer state wins
+    if !lost.is_empty() {
+        let names: Vec<String> = lost.iter().map(|new| new.unit.name.clone()).collect();
+        roll_back(name, lost, format!("{} changed during the upgrade", names.join(", ")));
+    }
+
+    let grace = Duration::from_millis(service.stop_timeout_millisecs);
+    for (unit, mut old) in retired {
+        println!("🛑 Stopping previous {} (pid {})", unit, old.id());
+        if let Err(e) = process::terminate(&unit, &mut old, grace) {
+            eprintln!("❌ Failed to stop previous {}: {}", unit, e);
+        }
+        // Its cgroup goes; the new process's becomes the unit's
+        limits::release(&unit);
+        #[cfg(unix)]
+        process::fifo::discard(&unit);
+    }
+    println!("✅ Upgraded {}", name);
+    Ok(switched)
+}